@@ -0,0 +1,149 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Socket activation
+//!
+//! With `socket_activation = true` Harbor binds the backend's Unix socket
+//! itself and hands the listening socket to the backend as file descriptor
+//! 3, following systemd's `LISTEN_FDS` protocol (understood by gunicorn,
+//! uvicorn and others). Connections made before the backend calls
+//! `accept()` wait in the listen backlog instead of being refused, and the
+//! socket is kept open across backend restarts.
+
+use crate::socket::BackendSocket;
+use std::process::Command;
+
+/// First file descriptor passed by the `LISTEN_FDS` protocol
+pub const LISTEN_FDS_START: i32 = 3;
+
+/// Shell wrapper that sets `LISTEN_PID` to the backend's own PID
+///
+/// The PID is only known after fork, and `std::process::Command` fixes the
+/// environment before that, so `sh` exports `$$` and then execs the backend
+/// in place, keeping the PID.
+const EXEC_WRAPPER: &str = r#"LISTEN_PID=$$; export LISTEN_PID; exec "$0" "$@""#;
+
+/// A listening socket owned by Harbor and passed to the backend
+#[derive(Debug)]
+pub struct ActivationSocket {
+    #[cfg(unix)]
+    listener: std::os::unix::net::UnixListener,
+    socket: BackendSocket,
+    name: String,
+}
+
+impl ActivationSocket {
+    /// Bind a listening socket, readable only by the current user
+    ///
+    /// `name` is passed to the backend in `LISTEN_FDNAMES`.
+    #[cfg(unix)]
+    pub fn bind(socket: &BackendSocket, name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: socket.bind()?,
+            socket: socket.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Bind a listening socket
+    #[cfg(not(unix))]
+    pub fn bind(_socket: &BackendSocket, _name: &str) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "socket activation requires Unix domain sockets",
+        ))
+    }
+
+    /// Get the socket address
+    pub fn socket(&self) -> &BackendSocket {
+        &self.socket
+    }
+
+    /// Build a command that runs `program` with this socket as fd 3
+    ///
+    /// Arguments, environment and stdio are added to the returned command as
+    /// usual.
+    #[cfg(unix)]
+    pub fn command(&self, program: &str) -> Command {
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(EXEC_WRAPPER).arg(program);
+        cmd.env("LISTEN_FDS", "1");
+        cmd.env("LISTEN_FDNAMES", &self.name);
+        cmd.env_remove("LISTEN_PID");
+
+        let fd = self.listener.as_raw_fd();
+        // SAFETY: the closure only makes the dup2/fcntl system calls, which
+        // are async-signal-safe, and allocates nothing
+        unsafe {
+            cmd.pre_exec(move || inherit_as_listen_fd(fd));
+        }
+        cmd
+    }
+
+    /// Build a command that runs `program` with this socket as fd 3
+    #[cfg(not(unix))]
+    pub fn command(&self, program: &str) -> Command {
+        Command::new(program)
+    }
+}
+
+impl Drop for ActivationSocket {
+    fn drop(&mut self) {
+        if let Some(path) = self.socket.path() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Move `fd` to `LISTEN_FDS_START` in the child and keep it open across exec
+#[cfg(unix)]
+fn inherit_as_listen_fd(fd: i32) -> std::io::Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use nix::unistd::dup2;
+
+    if fd == LISTEN_FDS_START {
+        // dup2 onto itself would leave close-on-exec set
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+    } else {
+        dup2(fd, LISTEN_FDS_START)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+
+    #[test]
+    fn test_backend_receives_socket() {
+        let path = std::env::temp_dir().join(format!("harbor-activation-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = ActivationSocket::bind(&BackendSocket::Path(path.clone()), "web").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // The child reports the protocol variables and answers on fd 3
+        let mut cmd = socket.command("/bin/sh");
+        cmd.arg("-c")
+            .arg(r#"echo "$LISTEN_FDS $LISTEN_FDNAMES $([ "$LISTEN_PID" = $$ ] && echo pid-ok)"; [ -e /dev/fd/3 ] && echo fd-ok"#)
+            .stdout(Stdio::piped());
+        let output = cmd.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1 web pid-ok\nfd-ok\n");
+
+        // Connections are queued even though nobody has accepted yet
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        assert!(stream.read(&mut [0u8; 1]).is_err());
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
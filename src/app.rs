@@ -4,17 +4,31 @@
 
 //! Harbor application runner
 
-use crate::backend::BackendManager;
-use crate::config::HarborConfig;
-use log::{error, info};
+use crate::backend::{BackendError, BackendManager};
+use crate::config::{BackendConfig, HarborConfig};
+use crate::health::{self, BackendState, HealthMonitor, StateCallback, StateCell, SupervisedBackend};
+use crate::logs::{self, LogLine, RotatingFile};
+use crate::validate::{self, Diagnostic};
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
 use thiserror::Error;
 
+/// File name for captured backend output in the app's log directory
+pub const BACKEND_LOG_FILE: &str = "backend.log";
+
+/// File name for Harbor's own log records in the app's log directory
+pub const HARBOR_LOG_FILE: &str = "harbor.log";
+
 /// Errors that can occur with Harbor apps
 #[derive(Debug, Error)]
 pub enum HarborError {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Invalid configuration: {}", summarize(.0))]
+    Invalid(Vec<Diagnostic>),
+
     #[error("Backend error: {0}")]
     Backend(#[from] crate::backend::BackendError),
 
@@ -25,25 +39,60 @@ pub enum HarborError {
     Io(#[from] std::io::Error),
 }
 
+/// List the errors among `diagnostics` for `HarborError::Invalid`
+fn summarize(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .map(|d| format!("{}: {}", d.key, d.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 /// A Harbor application instance
 pub struct HarborApp {
     config: HarborConfig,
-    backend: Option<BackendManager>,
+    /// Running backends, in startup order
+    backends: Vec<SupervisedBackend>,
+    monitor: Option<HealthMonitor>,
+    on_state_change: Option<StateCallback>,
 }
 
 impl HarborApp {
     /// Create a new Harbor app from configuration
-    pub fn new(config: HarborConfig) -> Self {
-        Self {
-            config,
-            backend: None,
+    ///
+    /// Fails if `HarborConfig::validate` reports errors; warnings are logged.
+    pub fn new(config: HarborConfig) -> Result<Self, HarborError> {
+        let diagnostics = config.validate();
+        if validate::has_errors(&diagnostics) {
+            return Err(HarborError::Invalid(diagnostics));
         }
+        for diagnostic in &diagnostics {
+            warn!("{}", diagnostic);
+        }
+
+        Ok(Self {
+            config,
+            backends: Vec::new(),
+            monitor: None,
+            on_state_change: None,
+        })
+    }
+
+    /// Call `callback` with the backend name on every backend state transition
+    ///
+    /// Must be set before the backends are started.
+    pub fn on_backend_state<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, &BackendState) + Send + Sync + 'static,
+    {
+        self.on_state_change = Some(Arc::new(callback));
     }
 
     /// Load a Harbor app from a TOML file
     pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
         let config = HarborConfig::load(path)?;
-        Ok(Self::new(config))
+        Ok(Self::new(config)?)
     }
 
     /// Get the application name
@@ -70,37 +119,146 @@ impl HarborApp {
         (self.config.frontend.width, self.config.frontend.height)
     }
 
-    /// Start the backend server
+    /// Start all backends in dependency order
+    ///
+    /// Each backend must be ready before the backends that depend on it are
+    /// started. If one fails to start, those already running are stopped.
     pub fn start_backend(&mut self) -> Result<(), HarborError> {
-        info!("Starting backend for app: {}", self.config.app.name);
+        info!("Starting backends for app: {}", self.config.app.name);
+
+        let order: Vec<BackendConfig> = self
+            .config
+            .startup_order()
+            .map_err(|e| HarborError::Config(e.to_string()))?
+            .into_iter()
+            .cloned()
+            .collect();
+
+        // All backends share one log file; lines are tagged with their name
+        let log_file = self.log_dir().and_then(|dir| {
+            let path = dir.join(BACKEND_LOG_FILE);
+            logs::open_shared(&path, self.config.logging.rotation())
+                .map_err(|e| warn!("Cannot open backend log {}: {}", path.display(), e))
+                .ok()
+        });
 
-        let mut backend = BackendManager::new(self.config.backend.clone());
-        backend.start()?;
+        for config in order {
+            let mut backend = BackendManager::new(config).with_app_name(&self.config.app.name);
+            if let Some(ref file) = log_file {
+                backend = backend.with_log_file(file.clone());
+            }
+
+            let state = StateCell::new(
+                backend.name(),
+                BackendState::Starting,
+                self.on_state_change.clone(),
+            );
+            if let Err(e) = backend.start() {
+                state.set(BackendState::Failed(e.to_string()));
+                // The start error is the one to report
+                if let Err(cleanup) = self.stop_backend() {
+                    error!("Error stopping backends after '{}' failed to start: {}", state.name(), cleanup);
+                }
+                return Err(e.into());
+            }
+            state.set(BackendState::Running);
+
+            self.backends.push(SupervisedBackend {
+                manager: Arc::new(Mutex::new(backend)),
+                state,
+            });
+        }
 
-        self.backend = Some(backend);
+        // Supervise the backends for as long as the app runs
+        self.monitor = Some(HealthMonitor::spawn(self.backends.clone())?);
         Ok(())
     }
 
-    /// Stop the backend server
+    /// Stop all backends, in reverse startup order
     pub fn stop_backend(&mut self) -> Result<(), HarborError> {
-        if let Some(ref mut backend) = self.backend {
-            backend.stop()?;
+        // Stop supervising first so the monitor doesn't restart them
+        if let Some(mut monitor) = self.monitor.take() {
+            monitor.stop();
         }
-        self.backend = None;
-        Ok(())
+
+        let mut result = Ok(());
+        while let Some(backend) = self.backends.pop() {
+            if let Err(e) = lock(&backend.manager).stop() {
+                error!("Error stopping backend '{}': {}", backend.state.name(), e);
+                result = Err(e.into());
+            }
+            if !backend.state.get().is_failed() {
+                backend.state.set(BackendState::Stopped);
+            }
+        }
+        result
     }
 
-    /// Check if backend is running and restart if needed
+    /// Check that no backend has failed for good
+    ///
+    /// Restarting crashed backends is left to the health monitor; this only
+    /// reports a backend it has given up on.
     pub fn check_backend(&mut self) -> Result<(), HarborError> {
-        if let Some(ref mut backend) = self.backend {
-            backend.check_and_restart()?;
+        for backend in &self.backends {
+            if let BackendState::Failed(reason) = backend.state.get() {
+                let message = format!("{}: {}", backend.state.name(), reason);
+                return Err(BackendError::Crashed(message).into());
+            }
         }
         Ok(())
     }
 
-    /// Get the backend socket path
-    pub fn socket_path(&self) -> &str {
-        &self.config.backend.socket
+    /// Get the current state of each backend, in startup order
+    pub fn backend_states(&self) -> Vec<(String, BackendState)> {
+        self.backends
+            .iter()
+            .map(|b| (b.state.name().to_string(), b.state.get()))
+            .collect()
+    }
+
+    /// Get the combined state of all backends
+    ///
+    /// This is `Failed` if any backend has failed for good, and `Running`
+    /// only when all of them are.
+    pub fn backend_state(&self) -> BackendState {
+        health::combined_state(&self.backend_states())
+    }
+
+    /// Get the directory log files are written to, if file logging is enabled
+    pub fn log_dir(&self) -> Option<PathBuf> {
+        self.config.logging.dir_for(&self.config.app.name)
+    }
+
+    /// Open this app's rotating Harbor log file
+    pub fn open_harbor_log(&self) -> Option<std::io::Result<RotatingFile>> {
+        self.log_dir().map(|dir| {
+            RotatingFile::open(dir.join(HARBOR_LOG_FILE), self.config.logging.rotation())
+        })
+    }
+
+    /// Get up to `n` of the most recent output lines across all backends
+    pub fn recent_logs(&self, n: usize) -> Vec<LogLine> {
+        let mut lines: Vec<LogLine> = self
+            .backends
+            .iter()
+            .flat_map(|b| lock(&b.manager).logs().recent(n))
+            .collect();
+        lines.sort_by_key(|line| line.timestamp);
+        let skip = lines.len().saturating_sub(n);
+        lines.split_off(skip)
+    }
+
+    /// Get up to `n` of the most recent output lines of one backend
+    pub fn backend_logs(&self, name: &str, n: usize) -> Option<Vec<LogLine>> {
+        self.backends
+            .iter()
+            .find(|b| b.state.name() == name)
+            .map(|b| lock(&b.manager).logs().recent(n))
+    }
+
+    /// Get the socket path of the backend the frontend talks to
+    pub fn socket_path(&self) -> Option<&str> {
+        self.config.primary_backend().map(|b| b.socket.as_str())
     }
 
     /// Run the Harbor app (starts backend, returns config for frontend)
@@ -143,6 +301,11 @@ impl Drop for HarborApp {
     }
 }
 
+/// Lock a shared backend manager, recovering from poisoning
+fn lock(backend: &Mutex<BackendManager>) -> MutexGuard<'_, BackendManager> {
+    backend.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Configuration returned by run() for creating the frontend window
 #[derive(Debug, Clone)]
 pub struct HarborRunConfig {
@@ -163,3 +326,53 @@ pub struct HarborRunConfig {
     /// Whether to enable devtools
     pub devtools: bool,
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_without_socket() {
+        let toml = r#"
+            [app]
+            name = "Harbor Worker Test"
+
+            [[backends]]
+            name = "api"
+            command = "sleep"
+            args = ["30"]
+            socket_activation = true
+
+            [[backends]]
+            name = "worker"
+            command = "sh"
+            args = ["-c", "echo worker ready; exec sleep 30"]
+            socket = "none"
+            depends_on = ["api"]
+            startup_timeout = 5
+            readiness = { type = "log-line", pattern = "worker ready" }
+
+            [frontend]
+            url = "http::unix//${socket}/"
+
+            [logging]
+            enabled = false
+        "#;
+        let config = HarborConfig::from_str(toml, "/").unwrap();
+        let mut app = HarborApp::new(config).unwrap();
+
+        app.start_backend().unwrap();
+        let states = app.backend_states();
+        assert_eq!(
+            states,
+            vec![
+                ("api".to_string(), BackendState::Running),
+                ("worker".to_string(), BackendState::Running),
+            ]
+        );
+        assert_eq!(app.socket_path(), Some(app.config().backends[0].socket.as_str()));
+
+        app.stop_backend().unwrap();
+        assert_eq!(app.backend_state(), BackendState::Stopped);
+    }
+}
@@ -4,13 +4,29 @@
 
 //! Backend server process management
 
-use crate::config::BackendConfig;
-use log::{debug, error, info, warn};
-use std::path::Path;
-use std::process::{Child, Command, Stdio};
+use crate::activation::ActivationSocket;
+use crate::config::{BackendConfig, StopSignal};
+use crate::logs::{LineObserver, LogBuffer, LogCapture, LogStream, SharedLogFile};
+use crate::readiness::{self, ReadinessStrategy};
+use crate::restart::{Failure, RestartDecision, RestartTracker};
+use crate::socket::BackendSocket;
+use log::{debug, error, info, warn, LevelFilter};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
+/// Longest single readiness check against an activated socket
+const ACTIVATED_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Delay between checks for a stopping backend to exit
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Longest wait for a response to the HTTP shutdown request
+const SHUTDOWN_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Errors that can occur with backend management
 #[derive(Debug, Error)]
 pub enum BackendError {
@@ -23,38 +39,161 @@ pub enum BackendError {
     #[error("Socket not ready after {0} seconds")]
     StartupTimeout(u64),
 
+    #[error("Readiness probe did not pass after {timeout} seconds (last result: {last})")]
+    HttpProbeTimeout { timeout: u64, last: String },
+
+    #[error("No backend output matched {pattern:?} within {timeout} seconds")]
+    LogLineTimeout { timeout: u64, pattern: String },
+
+    #[error("Readiness marker {} not created within {timeout} seconds", path.display())]
+    FileMarkerTimeout { timeout: u64, path: PathBuf },
+
+    #[error("Backend did not send READY=1 within {timeout} seconds ({last})")]
+    NotifyTimeout { timeout: u64, last: String },
+
+    #[error(
+        "Backend crash loop: {restarts} restarts within {window} seconds (recent failures: {})",
+        failures.join("; ")
+    )]
+    CrashLoop {
+        restarts: usize,
+        window: u64,
+        failures: Vec<String>,
+    },
+
+    #[error("Socket directory {} is world-writable without the sticky bit", dir.display())]
+    InsecureSocketDir { dir: PathBuf },
+
+    #[error("{} exists and is not a socket", path.display())]
+    NotASocket { path: PathBuf },
+
+    #[error("Refusing to remove socket {} owned by uid {owner}", path.display())]
+    ForeignSocket { path: PathBuf, owner: u32 },
+
+    #[error("Socket {} is owned by uid {owner}, not the current user", path.display())]
+    SocketOwner { path: PathBuf, owner: u32 },
+
+    #[error("Cannot restrict permissions of socket {}: {source}", path.display())]
+    SocketPermissions {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+/// How a backend was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// It was not running
+    NotRunning,
+    /// It exited after the HTTP shutdown request
+    ShutdownRequest,
+    /// It exited after the stop signal
+    Signal(StopSignal),
+    /// It did not exit within the stop timeout and was killed
+    Killed,
+}
+
+impl fmt::Display for StopOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopOutcome::NotRunning => write!(f, "was not running"),
+            StopOutcome::ShutdownRequest => write!(f, "exited after the shutdown request"),
+            StopOutcome::Signal(signal) => write!(f, "exited after {}", signal),
+            StopOutcome::Killed => write!(f, "was killed after the stop timeout"),
+        }
+    }
+}
+
 /// Manages the backend server process
 pub struct BackendManager {
     config: BackendConfig,
+    socket: BackendSocket,
     process: Option<Child>,
+    app_name: String,
+    logs: LogBuffer,
+    log_file: Option<SharedLogFile>,
+    restarts: RestartTracker,
+    /// Listening socket passed to the backend, kept across restarts
+    activation: Option<ActivationSocket>,
 }
 
 impl BackendManager {
     /// Create a new backend manager
     pub fn new(config: BackendConfig) -> Self {
+        let logs = LogBuffer::new(config.log_buffer_lines);
+        let restarts = RestartTracker::new(config.restart_policy(), config.restart.clone());
         Self {
+            socket: BackendSocket::parse(&config.socket),
             config,
             process: None,
+            app_name: String::from("harbor"),
+            logs,
+            log_file: None,
+            restarts,
+            activation: None,
         }
     }
 
+    /// Set the application name used to tag captured output
+    pub fn with_app_name(mut self, name: impl Into<String>) -> Self {
+        self.app_name = name.into();
+        self
+    }
+
+    /// Append captured output to a log file
+    pub fn with_log_file(mut self, file: SharedLogFile) -> Self {
+        self.log_file = Some(file);
+        self
+    }
+
+    /// Get the backend name used to tag captured output
+    pub fn name(&self) -> &str {
+        self.config.name()
+    }
+
+    /// Get the buffer of recent backend output
+    pub fn logs(&self) -> &LogBuffer {
+        &self.logs
+    }
+
     /// Start the backend server
     pub fn start(&mut self) -> Result<(), BackendError> {
-        info!("Starting backend: {} {:?}", self.config.command, self.config.args);
+        info!(
+            "Starting backend '{}': {} {:?}",
+            self.name(),
+            self.config.command,
+            self.config.args
+        );
+
+        // Clean up existing socket file if present, unless we hold it open
+        // from a previous run or there is no socket
+        if self.activation.is_none() && !self.socket.is_disabled() {
+            self.socket.create_dir()?;
+            self.socket.check_dir()?;
+            self.socket.remove_stale()?;
+        }
 
-        // Clean up existing socket file if present
-        let socket_path = Path::new(&self.config.socket);
-        if socket_path.exists() {
-            debug!("Removing existing socket: {}", self.config.socket);
-            std::fs::remove_file(socket_path)?;
+        if self.config.socket_activation && self.activation.is_none() {
+            let socket = ActivationSocket::bind(&self.socket, self.name()).map_err(|e| {
+                BackendError::StartFailed(format!("Failed to bind socket {}: {}", self.socket, e))
+            })?;
+            info!("Listening on {} for backend '{}'", self.socket, self.name());
+            self.activation = Some(socket);
         }
 
+        let mut strategy = match self.config.readiness {
+            Some(ref readiness) => Some(readiness::from_config(readiness, &self.app_name, self.name())?),
+            None => None,
+        };
+
         // Build command
-        let mut cmd = Command::new(&self.config.command);
+        let mut cmd = match self.activation {
+            Some(ref socket) => socket.command(&self.config.command),
+            None => Command::new(&self.config.command),
+        };
         cmd.args(&self.config.args);
 
         // Set working directory
@@ -64,13 +203,8 @@ impl BackendManager {
 
         // Ensure PATH includes common locations for user-installed tools
         // This allows finding gunicorn, uvicorn, etc. installed via pip --user
-        if let Ok(current_path) = std::env::var("PATH") {
-            if let Some(home) = std::env::var("HOME").ok() {
-                let local_bin = format!("{}/.local/bin", home);
-                if !current_path.contains(&local_bin) {
-                    cmd.env("PATH", format!("{}:{}", local_bin, current_path));
-                }
-            }
+        if let Some(path) = search_path(&self.config) {
+            cmd.env("PATH", path);
         }
 
         // Set environment variables from config
@@ -82,98 +216,255 @@ impl BackendManager {
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
+        if let Some(ref mut strategy) = strategy {
+            strategy.prepare(&mut cmd)?;
+        }
+
         // Spawn process
-        let child = cmd.spawn().map_err(|e| {
+        let mut child = cmd.spawn().map_err(|e| {
             BackendError::StartFailed(format!("Failed to spawn {}: {}", self.config.command, e))
         })?;
 
+        // Drain output so the child never blocks on a full pipe
+        let observer = strategy.as_ref().and_then(|s| s.observer());
+        self.capture_output(&mut child, observer)?;
+
         self.process = Some(child);
-        info!("Backend process started");
+        info!("Backend '{}' process started", self.name());
 
         // Wait for socket to be ready
-        self.wait_for_socket()?;
+        self.wait_for_socket(strategy.as_mut())?;
+        if !self.socket.is_disabled() {
+            self.socket.secure()?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawn reader threads for the child's stdout and stderr
+    fn capture_output(
+        &self,
+        child: &mut Child,
+        observer: Option<LineObserver>,
+    ) -> Result<(), BackendError> {
+        let level = self.config.log_level.parse().unwrap_or_else(|_| {
+            warn!(
+                "Invalid backend log_level '{}', using info",
+                self.config.log_level
+            );
+            LevelFilter::Info
+        });
+        let mut capture = LogCapture::new(&self.app_name, self.name(), level, self.logs.clone());
+        if let Some(ref file) = self.log_file {
+            capture = capture.with_file(file.clone());
+        }
+        if let Some(observer) = observer {
+            capture = capture.with_observer(observer);
+        }
+
+        if let Some(stdout) = child.stdout.take() {
+            capture.spawn(LogStream::Stdout, stdout)?;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            capture.spawn(LogStream::Stderr, stderr)?;
+        }
 
         Ok(())
     }
 
     /// Wait for the backend socket to be ready
-    fn wait_for_socket(&mut self) -> Result<(), BackendError> {
-        let socket_path = Path::new(&self.config.socket);
+    ///
+    /// The socket must accept connections and, if configured, the readiness
+    /// strategy must pass, both within `startup_timeout`. An activated socket
+    /// accepts connections from the start, and a backend without a socket
+    /// has none to wait for, so then only the strategy is checked.
+    fn wait_for_socket(
+        &mut self,
+        strategy: Option<&mut Box<dyn ReadinessStrategy>>,
+    ) -> Result<(), BackendError> {
         let start = Instant::now();
         let timeout = Duration::from_secs(self.config.startup_timeout);
 
-        info!("Waiting for socket: {}", self.config.socket);
-
-        while start.elapsed() < timeout {
-            if socket_path.exists() {
-                // Try to connect to verify it's ready
+        if self.activation.is_some() || self.socket.is_disabled() {
+            self.check_alive()?;
+        } else {
+            info!("Waiting for socket: {}", self.socket);
+            loop {
+                // Try to connect to verify it's ready; abstract sockets have
+                // no file to wait for
                 #[cfg(unix)]
                 {
-                    use std::os::unix::net::UnixStream;
-                    if UnixStream::connect(socket_path).is_ok() {
-                        info!("Socket ready: {}", self.config.socket);
-                        return Ok(());
+                    if self.socket.connect().is_ok() {
+                        info!("Socket ready: {}", self.socket);
+                        break;
                     }
                 }
 
-                // Socket file exists, might be ready
-                debug!("Socket file exists, checking connectivity...");
-            }
+                if self.socket.path().is_some_and(|p| p.exists()) {
+                    // Socket file exists, might be ready
+                    debug!("Socket file exists, checking connectivity...");
+                }
+
+                self.check_alive()?;
 
-            // Check if process is still running
-            if let Some(ref mut child) = self.process {
-                if let Ok(Some(status)) = child.try_wait() {
-                    return Err(BackendError::Crashed(format!(
-                        "Backend exited with status: {}",
-                        status
-                    )));
+                if start.elapsed() >= timeout {
+                    return Err(BackendError::StartupTimeout(self.config.startup_timeout));
                 }
+                std::thread::sleep(Duration::from_millis(100));
             }
+        }
 
-            std::thread::sleep(Duration::from_millis(100));
+        if let Some(strategy) = strategy {
+            self.wait_for_readiness(strategy.as_mut(), start, timeout)?;
         }
 
-        Err(BackendError::StartupTimeout(self.config.startup_timeout))
+        Ok(())
     }
 
-    /// Stop the backend server
-    pub fn stop(&mut self) -> Result<(), BackendError> {
+    /// Poll a readiness strategy until it passes or `timeout` elapses
+    fn wait_for_readiness(
+        &mut self,
+        strategy: &mut dyn ReadinessStrategy,
+        start: Instant,
+        timeout: Duration,
+    ) -> Result<(), BackendError> {
+        let socket = self.socket.clone();
+
+        loop {
+            let mut remaining = timeout.saturating_sub(start.elapsed());
+            if self.activation.is_some() {
+                // Requests to an activated socket queue until the backend
+                // accepts, so keep each attempt short enough to notice a crash
+                remaining = remaining.min(ACTIVATED_PROBE_TIMEOUT);
+            }
+            let result = strategy.check(&socket, remaining.max(Duration::from_millis(100)));
+            if result.is_ready() {
+                info!("Backend '{}' ready ({} check passed)", self.name(), strategy.name());
+                return Ok(());
+            }
+            debug!("Readiness ({}): {}", strategy.name(), result);
+
+            self.check_alive()?;
+
+            if start.elapsed() >= timeout {
+                return Err(strategy.timeout_error(self.config.startup_timeout, &result));
+            }
+            std::thread::sleep(strategy.interval());
+        }
+    }
+
+    /// Fail if the backend process has already exited
+    fn check_alive(&mut self) -> Result<(), BackendError> {
         if let Some(ref mut child) = self.process {
-            info!("Stopping backend process");
+            if let Ok(Some(status)) = child.try_wait() {
+                return Err(BackendError::Crashed(format!(
+                    "Backend exited with status: {}",
+                    status
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the backend server and close its socket
+    ///
+    /// The backend is asked to stop with the shutdown request, if
+    /// configured, and then the stop signal, and killed if it is still
+    /// running `stop_timeout` seconds after the first of these.
+    /// Returns which of these stopped it.
+    pub fn stop(&mut self) -> Result<StopOutcome, BackendError> {
+        let outcome = self.stop_process()?;
+
+        // Clean up socket file
+        self.activation = None;
+        if let Err(e) = self.socket.remove_stale() {
+            warn!("Not removing socket of backend '{}': {}", self.name(), e);
+        }
 
-            // Try graceful shutdown first
-            #[cfg(unix)]
-            {
-                use nix::sys::signal::{kill, Signal};
-                use nix::unistd::Pid;
+        Ok(outcome)
+    }
 
-                if let Ok(pid) = child.id().try_into() {
-                    let _ = kill(Pid::from_raw(pid), Signal::SIGTERM);
+    /// Stop the backend process, leaving an activated socket open
+    fn stop_process(&mut self) -> Result<StopOutcome, BackendError> {
+        let Some(mut child) = self.process.take() else {
+            return Ok(StopOutcome::NotRunning);
+        };
+        if child.try_wait()?.is_some() {
+            return Ok(StopOutcome::NotRunning);
+        }
+
+        info!("Stopping backend '{}'", self.name());
+        let started = Instant::now();
+        let outcome = self.shut_down(&mut child)?;
+        child.wait()?;
+
+        match outcome {
+            StopOutcome::Killed => warn!(
+                "Backend '{}' did not exit within {} seconds and was killed",
+                self.name(),
+                self.config.stop_timeout
+            ),
+            _ => info!("Backend '{}' {} in {:?}", self.name(), outcome, started.elapsed()),
+        }
+        Ok(outcome)
+    }
 
-                    // Wait a bit for graceful shutdown
-                    std::thread::sleep(Duration::from_secs(2));
+    /// Ask the backend to stop, more and more forcefully, until it exits
+    ///
+    /// The shutdown request and the stop signal share one `stop_timeout`.
+    fn shut_down(&self, child: &mut Child) -> Result<StopOutcome, BackendError> {
+        let deadline = Instant::now() + Duration::from_secs(self.config.stop_timeout);
+
+        if let Some(ref path) = self.config.shutdown_path {
+            match crate::http::request(&self.socket, "POST", path, None, SHUTDOWN_REQUEST_TIMEOUT) {
+                Ok(response) => {
+                    debug!("Shutdown request to backend '{}': {}", self.name(), response.status);
+                    if wait_for_exit(child, deadline)? {
+                        return Ok(StopOutcome::ShutdownRequest);
+                    }
                 }
+                // A backend may drop the connection as it exits
+                Err(_) if child.try_wait()?.is_some() => return Ok(StopOutcome::ShutdownRequest),
+                Err(e) => warn!("Shutdown request to backend '{}' failed: {}", self.name(), e),
             }
+        }
 
-            // Force kill if still running
-            if child.try_wait()?.is_none() {
-                warn!("Backend didn't stop gracefully, forcing kill");
-                child.kill()?;
+        #[cfg(unix)]
+        {
+            use nix::sys::signal::{kill, Signal};
+            use nix::unistd::Pid;
+
+            let signal = match self.config.stop_signal {
+                StopSignal::Term => Signal::SIGTERM,
+                StopSignal::Int => Signal::SIGINT,
+                StopSignal::Quit => Signal::SIGQUIT,
+                StopSignal::Hup => Signal::SIGHUP,
+            };
+            if let Ok(pid) = child.id().try_into() {
+                if kill(Pid::from_raw(pid), signal).is_ok() && wait_for_exit(child, deadline)? {
+                    return Ok(StopOutcome::Signal(self.config.stop_signal));
+                }
             }
-
-            child.wait()?;
-            info!("Backend process stopped");
         }
 
-        self.process = None;
+        child.kill()?;
+        Ok(StopOutcome::Killed)
+    }
 
-        // Clean up socket file
-        let socket_path = Path::new(&self.config.socket);
-        if socket_path.exists() {
-            let _ = std::fs::remove_file(socket_path);
-        }
+    /// Stop and start the backend again
+    ///
+    /// With socket activation the socket stays open in between, so clients
+    /// connecting during the restart wait instead of being refused.
+    pub fn restart(&mut self) -> Result<(), BackendError> {
+        self.stop_process()?;
+        self.start()
+    }
 
-        Ok(())
+    /// Get the exit status if the backend process has exited
+    pub fn exit_status(&mut self) -> Option<ExitStatus> {
+        self.process
+            .as_mut()
+            .and_then(|child| child.try_wait().ok().flatten())
     }
 
     /// Check if the backend is running
@@ -189,22 +480,59 @@ impl BackendManager {
         }
     }
 
-    /// Restart the backend if it crashed
-    pub fn check_and_restart(&mut self) -> Result<bool, BackendError> {
-        if !self.is_running() && self.config.restart_on_crash {
-            warn!("Backend crashed, restarting...");
-            self.process = None;
-            self.start()?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    /// Record a failure and decide, per the restart policy, whether to restart
+    pub fn record_failure(&mut self, failure: &Failure) -> RestartDecision {
+        self.restarts.decide(failure)
+    }
+
+    /// Get the backend configuration
+    pub fn config(&self) -> &BackendConfig {
+        &self.config
     }
 
-    /// Get the socket path
+    /// Get the socket path as configured
     pub fn socket_path(&self) -> &str {
         &self.config.socket
     }
+
+    /// Get the socket address
+    pub fn socket(&self) -> &BackendSocket {
+        &self.socket
+    }
+}
+
+/// PATH the backend's command is looked up on
+///
+/// This is the `PATH` in the backend's `env` if it sets one, or else
+/// Harbor's own with `~/.local/bin` (where `pip install --user` puts
+/// gunicorn, uvicorn and the like) in front.
+pub(crate) fn search_path(config: &BackendConfig) -> Option<OsString> {
+    if let Some(path) = config.env.get("PATH") {
+        return Some(path.into());
+    }
+    let current = std::env::var_os("PATH")?;
+    let Some(home) = std::env::var_os("HOME") else {
+        return Some(current);
+    };
+    let local_bin = PathBuf::from(home).join(".local/bin");
+    let mut dirs: Vec<PathBuf> = std::env::split_paths(&current).collect();
+    if !dirs.contains(&local_bin) {
+        dirs.insert(0, local_bin);
+    }
+    std::env::join_paths(dirs).ok().or(Some(current))
+}
+
+/// Wait until `deadline` for `child` to exit; returns whether it did
+fn wait_for_exit(child: &mut Child, deadline: Instant) -> std::io::Result<bool> {
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(STOP_POLL_INTERVAL);
+    }
 }
 
 impl Drop for BackendManager {
@@ -214,3 +542,45 @@ impl Drop for BackendManager {
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_path() {
+        let mut config: BackendConfig = toml::from_str("command = \"gunicorn\"").unwrap();
+        if let Some(home) = std::env::var_os("HOME") {
+            let path = search_path(&config).unwrap();
+            let first = std::env::split_paths(&path).next();
+            assert_eq!(first, Some(PathBuf::from(home).join(".local/bin")));
+        }
+
+        config.env.insert("PATH".to_string(), "/opt/app/bin".to_string());
+        assert_eq!(search_path(&config), Some(OsString::from("/opt/app/bin")));
+    }
+
+    #[test]
+    fn test_stop_outcome() {
+        let manager = |script: &str, config: &str| {
+            let toml = format!("command = \"sh\"\nsocket = \"/tmp/harbor-stop-test.sock\"\n{}", config);
+            let mut manager = BackendManager::new(toml::from_str(&toml).unwrap());
+            manager.process = Some(Command::new("sh").args(["-c", script]).spawn().unwrap());
+            // Let the shell set up its traps
+            std::thread::sleep(Duration::from_millis(200));
+            manager
+        };
+
+        let started = Instant::now();
+        let mut sleeper = manager("sleep 30", "");
+        assert_eq!(sleeper.stop().unwrap(), StopOutcome::Signal(StopSignal::Term));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let mut quitter = manager("trap '' TERM; trap 'exit 0' QUIT; while :; do sleep 0.05; done", "stop_signal = \"QUIT\"");
+        assert_eq!(quitter.stop().unwrap(), StopOutcome::Signal(StopSignal::Quit));
+
+        let mut stubborn = manager("trap '' TERM; while :; do sleep 0.05; done", "stop_timeout = 0");
+        assert_eq!(stubborn.stop().unwrap(), StopOutcome::Killed);
+        assert_eq!(stubborn.stop().unwrap(), StopOutcome::NotRunning);
+    }
+}
@@ -0,0 +1,482 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Building a configuration in code
+//!
+//! For launchers that embed Harbor rather than ship an app.toml:
+//!
+//! ```no_run
+//! use harbor::config::{BackendConfig, FrontendConfig, HarborConfig};
+//!
+//! let config = HarborConfig::builder()
+//!     .app("Reports")
+//!     .backend(BackendConfig::builder().command("gunicorn").args(["app:create_app()"]))
+//!     .frontend(FrontendConfig::builder().url("http::unix//${socket}/").width(1400))
+//!     .build()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The builders collect the values they are given into a table that is
+//! loaded like app.toml, so a built config gets the same defaults, `auto`
+//! sockets, `${...}` variables and path resolution as one read from a file.
+//! `build()` fails on anything `harbor check` reports as an error.
+
+use crate::config::{
+    AppConfig, BackendConfig, FrontendConfig, HarborConfig, HealthConfig, LoadOptions,
+    LoggingConfig, ReadinessConfig, RestartConfig, SettingsConfig, StopSignal,
+};
+use crate::layers::{Layer, Source};
+use crate::validate::Diagnostic;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Values set on a builder, by key
+#[derive(Debug, Clone, Default)]
+struct Fields {
+    table: toml::Table,
+    /// The first value that could not be represented, reported by `build()`
+    error: Option<String>,
+}
+
+impl Fields {
+    /// `value` as TOML, recording the error if it cannot be
+    fn value(&mut self, key: &str, value: impl Serialize) -> Option<toml::Value> {
+        toml::Value::try_from(value)
+            .map_err(|e| self.error.get_or_insert_with(|| format!("{}: {}", key, e)))
+            .ok()
+    }
+
+    fn set(&mut self, key: &str, value: impl Serialize) {
+        if let Some(value) = self.value(key, value) {
+            self.table.insert(key.to_string(), value);
+        }
+    }
+
+    fn push(&mut self, key: &str, value: impl Serialize) {
+        let Some(value) = self.value(key, value) else {
+            return;
+        };
+        let items = self
+            .table
+            .entry(key)
+            .or_insert_with(|| toml::Value::Array(Vec::new()));
+        if let toml::Value::Array(items) = items {
+            items.push(value);
+        }
+    }
+
+    /// Add the fields of a nested builder as the table at `key`
+    fn nest(&mut self, key: &str, fields: Fields) {
+        self.error = self.error.take().or(fields.error);
+        self.table.insert(key.to_string(), toml::Value::Table(fields.table));
+    }
+}
+
+impl HarborConfig {
+    /// Start building a configuration in code
+    pub fn builder() -> HarborConfigBuilder {
+        HarborConfigBuilder::default()
+    }
+}
+
+/// Builder for a `HarborConfig`; see `HarborConfig::builder()`
+#[derive(Debug, Clone, Default)]
+pub struct HarborConfigBuilder {
+    fields: Fields,
+    app_dir: Option<PathBuf>,
+    options: LoadOptions,
+}
+
+impl HarborConfigBuilder {
+    /// Set the application name
+    pub fn app(self, name: impl Into<String>) -> Self {
+        self.app_config(AppConfig::builder().name(name))
+    }
+
+    /// Set the application metadata
+    pub fn app_config(mut self, app: AppConfigBuilder) -> Self {
+        self.fields.nest("app", app.fields);
+        self
+    }
+
+    /// Set the backend (the single-backend form)
+    pub fn backend(mut self, backend: BackendConfigBuilder) -> Self {
+        self.fields.nest("backend", backend.fields);
+        self
+    }
+
+    /// Add a named backend, started in dependency order like `[[backends]]`
+    pub fn add_backend(mut self, backend: BackendConfigBuilder) -> Self {
+        if let Some(error) = backend.fields.error {
+            self.fields.error.get_or_insert(error);
+        }
+        self.fields.push("backends", backend.fields.table);
+        self
+    }
+
+    /// Set the frontend window configuration
+    pub fn frontend(mut self, frontend: FrontendConfigBuilder) -> Self {
+        self.fields.nest("frontend", frontend.fields);
+        self
+    }
+
+    /// Set the additional settings
+    pub fn settings(mut self, settings: SettingsConfig) -> Self {
+        self.fields.set("settings", settings);
+        self
+    }
+
+    /// Set the log file settings
+    pub fn logging(mut self, logging: LoggingConfig) -> Self {
+        self.fields.set("logging", logging);
+        self
+    }
+
+    /// Set the directory relative paths are resolved against, and the
+    /// value of `${app_dir}` (default: the current directory)
+    pub fn app_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.app_dir = Some(dir.into());
+        self
+    }
+
+    /// Apply layers over the built values, such as the user's override
+    /// file and `HARBOR_*` environment variables
+    ///
+    /// By default none are applied.
+    pub fn options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Build the configuration, filling in defaults, and validate it
+    ///
+    /// Fails if a required value is missing or `HarborConfig::validate`
+    /// finds an error. Warnings are left for the caller to check.
+    pub fn build(self) -> anyhow::Result<HarborConfig> {
+        if let Some(error) = self.fields.error {
+            anyhow::bail!(error);
+        }
+        let app_dir = self.app_dir.unwrap_or_default();
+        let config = HarborConfig::parse(
+            self.fields.table,
+            Layer::new(Source::Builder),
+            &app_dir,
+            Path::new(""),
+            &self.options,
+        )?;
+
+        let errors: Vec<String> = config
+            .validate()
+            .iter()
+            .filter(|d| d.is_error())
+            .map(Diagnostic::to_string)
+            .collect();
+        if !errors.is_empty() {
+            anyhow::bail!(errors.join("\n"));
+        }
+        Ok(config)
+    }
+}
+
+impl AppConfig {
+    /// Start building the application metadata
+    pub fn builder() -> AppConfigBuilder {
+        AppConfigBuilder::default()
+    }
+}
+
+/// Builder for `[app]`
+#[derive(Debug, Clone, Default)]
+pub struct AppConfigBuilder {
+    fields: Fields,
+}
+
+impl AppConfigBuilder {
+    /// Application name
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.fields.set("name", name.into());
+        self
+    }
+
+    /// Application version
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.fields.set("version", version.into());
+        self
+    }
+
+    /// Application icon path
+    pub fn icon(mut self, icon: impl Into<PathBuf>) -> Self {
+        self.fields.set("icon", icon.into());
+        self
+    }
+
+    /// Application description
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.fields.set("description", description.into());
+        self
+    }
+}
+
+impl BackendConfig {
+    /// Start building a backend
+    pub fn builder() -> BackendConfigBuilder {
+        BackendConfigBuilder::default()
+    }
+}
+
+/// Builder for `[backend]` or one of `[[backends]]`
+#[derive(Debug, Clone, Default)]
+pub struct BackendConfigBuilder {
+    fields: Fields,
+}
+
+impl BackendConfigBuilder {
+    /// Backend name (defaults to the command's file name)
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.fields.set("name", name.into());
+        self
+    }
+
+    /// Add a backend that must be ready before this one starts
+    pub fn depends_on(mut self, name: impl Into<String>) -> Self {
+        self.fields.push("depends_on", name.into());
+        self
+    }
+
+    /// Command to run the backend
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.fields.set("command", command.into());
+        self
+    }
+
+    /// Add an argument to pass to the command
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.fields.push("args", arg.into());
+        self
+    }
+
+    /// Add arguments to pass to the command
+    pub fn args<I, S>(self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        args.into_iter().fold(self, Self::arg)
+    }
+
+    /// Socket path, `@name` for an abstract socket, or "auto"
+    pub fn socket(mut self, socket: impl Into<String>) -> Self {
+        self.fields.set("socket", socket.into());
+        self
+    }
+
+    /// Bind the socket in Harbor and pass it to the backend
+    pub fn socket_activation(mut self, enabled: bool) -> Self {
+        self.fields.set("socket_activation", enabled);
+        self
+    }
+
+    /// Working directory for the backend process
+    pub fn workdir(mut self, workdir: impl Into<PathBuf>) -> Self {
+        self.fields.set("workdir", workdir.into());
+        self
+    }
+
+    /// Set an environment variable
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let env = self
+            .fields
+            .table
+            .entry("env")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let toml::Value::Table(env) = env {
+            env.insert(name.into(), toml::Value::String(value.into()));
+        }
+        self
+    }
+
+    /// Startup timeout in seconds
+    pub fn startup_timeout(mut self, secs: u64) -> Self {
+        self.fields.set("startup_timeout", secs);
+        self
+    }
+
+    /// Restart policy, limits and backoff
+    pub fn restart(mut self, restart: RestartConfig) -> Self {
+        self.fields.set("restart", restart);
+        self
+    }
+
+    /// Level at which captured output lines are logged ("off" to disable)
+    pub fn log_level(mut self, level: impl Into<String>) -> Self {
+        self.fields.set("log_level", level.into());
+        self
+    }
+
+    /// Number of recent output lines kept in memory
+    pub fn log_buffer_lines(mut self, lines: usize) -> Self {
+        self.fields.set("log_buffer_lines", lines);
+        self
+    }
+
+    /// Readiness check that must pass before the backend counts as ready
+    pub fn readiness(mut self, readiness: ReadinessConfig) -> Self {
+        self.fields.set("readiness", readiness);
+        self
+    }
+
+    /// Health monitoring once the backend is running
+    pub fn health(mut self, health: HealthConfig) -> Self {
+        self.fields.set("health", health);
+        self
+    }
+
+    /// Signal asking the backend to stop
+    pub fn stop_signal(mut self, signal: StopSignal) -> Self {
+        self.fields.set("stop_signal", signal);
+        self
+    }
+
+    /// Seconds to wait for the backend to exit before killing it
+    pub fn stop_timeout(mut self, secs: u64) -> Self {
+        self.fields.set("stop_timeout", secs);
+        self
+    }
+
+    /// HTTP endpoint sent a POST to ask the backend to shut down
+    pub fn shutdown_path(mut self, path: impl Into<String>) -> Self {
+        self.fields.set("shutdown_path", path.into());
+        self
+    }
+}
+
+impl FrontendConfig {
+    /// Start building the frontend window configuration
+    pub fn builder() -> FrontendConfigBuilder {
+        FrontendConfigBuilder::default()
+    }
+}
+
+/// Builder for `[frontend]`
+#[derive(Debug, Clone, Default)]
+pub struct FrontendConfigBuilder {
+    fields: Fields,
+}
+
+impl FrontendConfigBuilder {
+    /// URL to load, such as "http::unix//${socket}/"
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.fields.set("url", url.into());
+        self
+    }
+
+    /// Window width
+    pub fn width(mut self, width: u32) -> Self {
+        self.fields.set("width", width);
+        self
+    }
+
+    /// Window height
+    pub fn height(mut self, height: u32) -> Self {
+        self.fields.set("height", height);
+        self
+    }
+
+    /// Window title (defaults to app name)
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.fields.set("title", title.into());
+        self
+    }
+
+    /// Whether the window is resizable
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.fields.set("resizable", resizable);
+        self
+    }
+
+    /// Whether to show the window frame
+    pub fn decorated(mut self, decorated: bool) -> Self {
+        self.fields.set("decorated", decorated);
+        self
+    }
+
+    /// Whether to start fullscreen
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fields.set("fullscreen", fullscreen);
+        self
+    }
+
+    /// Minimum window size
+    pub fn min_size(mut self, width: u32, height: u32) -> Self {
+        self.fields.set("min_size", (width, height));
+        self
+    }
+
+    /// Maximum window size
+    pub fn max_size(mut self, width: u32, height: u32) -> Self {
+        self.fields.set("max_size", (width, height));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RestartPolicy;
+
+    #[test]
+    fn test_builder() {
+        let toml = r#"
+[app]
+name = "Built"
+
+[backend]
+command = "sh"
+args = ["-c", "sleep 1"]
+env = { MODE = "test" }
+restart = { policy = "never" }
+
+[frontend]
+url = "http::unix//${socket}/"
+width = 1400
+"#;
+        let parsed = HarborConfig::from_str(toml, "/srv/built").unwrap();
+        let built = HarborConfig::builder()
+            .app("Built")
+            .backend(
+                BackendConfig::builder()
+                    .command("sh")
+                    .args(["-c", "sleep 1"])
+                    .env("MODE", "test")
+                    .restart(RestartConfig {
+                        policy: Some(RestartPolicy::Never),
+                        ..RestartConfig::default()
+                    }),
+            )
+            .frontend(FrontendConfig::builder().url("http::unix//${socket}/").width(1400))
+            .app_dir("/srv/built")
+            .build()
+            .unwrap();
+        assert_eq!(toml::to_string(&built).unwrap(), toml::to_string(&parsed).unwrap());
+        assert_eq!(built.sources.source_of("frontend.width"), Source::Builder);
+        assert_eq!(built.sources.source_of("frontend.height"), Source::Default);
+
+        let missing = HarborConfig::builder()
+            .app("Built")
+            .backend(BackendConfig::builder().name("web"))
+            .frontend(FrontendConfig::builder().url("http::unix//${socket}/"))
+            .build();
+        assert!(missing.unwrap_err().to_string().contains("missing field `command`"));
+
+        let invalid = HarborConfig::builder()
+            .app("Built")
+            .backend(BackendConfig::builder().command("sh"))
+            .frontend(FrontendConfig::builder().url("http::unix//${socket}/").width(0))
+            .build();
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            "error: frontend: window size 0x768 has a zero dimension"
+        );
+    }
+}
@@ -4,39 +4,351 @@
 
 //! Harbor application configuration
 
+use anyhow::Context;
+use crate::interpolate::{interpolate_value, join_key, Variables};
+use crate::layers::{self, Layer, Provenance, Source};
+use crate::logs::RotationPolicy;
+use crate::validate::Diagnostic;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `backend.socket` value that lets Harbor choose the socket path
+pub const AUTO_SOCKET: &str = "auto";
+
+/// `backend.socket` value for a backend that does not listen on a socket,
+/// such as a worker
+pub const NO_SOCKET: &str = "none";
+
+/// Environment variable selecting a profile when none is given explicitly
+pub const PROFILE_ENV: &str = "HARBOR_PROFILE";
+
+/// How to load a configuration
+///
+/// By default only the config file itself is read; `layered()` adds the
+/// user's override file and `HARBOR_*` environment variables.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// `[profile.<name>]` to merge over the base configuration; falls back
+    /// to `$HARBOR_PROFILE`
+    pub profile: Option<String>,
+
+    /// Merge `~/.config/harbor/<app>.toml` over the config file
+    pub user_config: bool,
+
+    /// Apply `HARBOR_*__*` environment variables
+    pub env: bool,
+
+    /// `key.path=value` assignments applied last, as given to `--set`
+    pub sets: Vec<String>,
+}
+
+impl LoadOptions {
+    /// Options applying every layer: the user's override file and
+    /// environment variables
+    pub fn layered() -> Self {
+        Self {
+            user_config: true,
+            env: true,
+            ..Self::default()
+        }
+    }
+
+    /// Select a profile
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Add a `key.path=value` assignment
+    pub fn with_set(mut self, assignment: impl Into<String>) -> Self {
+        self.sets.push(assignment.into());
+        self
+    }
+}
 
 /// Main Harbor configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HarborConfig {
+    /// Layout version of the file; older layouts are upgraded on load
+    #[serde(default = "default_config_version")]
+    pub config_version: i64,
+
     /// Application metadata
     pub app: AppConfig,
 
-    /// Backend server configuration
+    /// Backend server configuration (single-backend form)
+    ///
+    /// Without a `[backend]` section this is unset (see
+    /// `BackendConfig::is_unset`) and the backends are in `backends`.
+    #[serde(default, skip_serializing_if = "BackendConfig::is_unset")]
     pub backend: BackendConfig,
 
+    /// Named backend servers (`[[backends]]`), started in dependency order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backends: Vec<BackendConfig>,
+
     /// Frontend window configuration
     pub frontend: FrontendConfig,
 
     /// Optional: Additional settings
     #[serde(default)]
     pub settings: SettingsConfig,
+
+    /// Optional: Log file settings
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    /// Directory relative paths are resolved against: the directory
+    /// containing app.toml
+    #[serde(skip)]
+    pub app_dir: PathBuf,
+
+    /// Keys in the file that Harbor does not read, found while parsing
+    #[serde(skip)]
+    pub unknown_keys: Vec<Diagnostic>,
+
+    /// Old layouts upgraded while parsing
+    #[serde(skip)]
+    pub deprecations: Vec<Diagnostic>,
+
+    /// The profile merged over the base configuration, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Which layer each value came from
+    #[serde(skip)]
+    pub sources: Provenance,
 }
 
 impl HarborConfig {
     /// Load configuration from a TOML file
-    pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+    ///
+    /// Relative paths in the file are resolved against its directory. The
+    /// file may also be a pyproject.toml or package.json holding the
+    /// configuration; see `crate::manifest`.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::load_with(path, &LoadOptions::default())
+    }
+
+    /// Load configuration from a TOML file with options such as a profile
+    pub fn load_with<P: AsRef<Path>>(path: P, options: &LoadOptions) -> anyhow::Result<Self> {
+        let path = path.as_ref();
         let contents = std::fs::read_to_string(path)?;
-        let config: HarborConfig = toml::from_str(&contents)?;
-        Ok(config)
+        let app_dir = path.parent().unwrap_or(Path::new(""));
+        let (table, layer) = crate::manifest::read(path, &contents)?;
+        Self::parse(table, layer, app_dir, path, options)
     }
 
     /// Load configuration from string
-    pub fn from_str(toml_str: &str) -> anyhow::Result<Self> {
-        let config: HarborConfig = toml::from_str(toml_str)?;
+    ///
+    /// Relative paths are resolved against `app_dir`, which is also the
+    /// value of `${app_dir}`; a relative `app_dir` is taken from the current
+    /// directory.
+    pub fn from_str<P: AsRef<Path>>(toml_str: &str, app_dir: P) -> anyhow::Result<Self> {
+        Self::from_str_with(toml_str, app_dir, &LoadOptions::default())
+    }
+
+    /// Load configuration from string with options such as a profile
+    pub fn from_str_with<P: AsRef<Path>>(
+        toml_str: &str,
+        app_dir: P,
+        options: &LoadOptions,
+    ) -> anyhow::Result<Self> {
+        let file = Path::new("app.toml");
+        let layer = Layer::from_file(Source::file(file), toml_str);
+        Self::parse(toml::from_str(toml_str)?, layer, app_dir.as_ref(), file, options)
+    }
+
+    /// Layer, interpolate and deserialize a configuration
+    ///
+    /// `table` is the configuration read from `file` as `layer`.
+    pub(crate) fn parse(
+        table: toml::Table,
+        layer: Layer,
+        app_dir: &Path,
+        file: &Path,
+        options: &LoadOptions,
+    ) -> anyhow::Result<Self> {
+        let app_dir = resolve_path(app_dir, &std::env::current_dir()?);
+        let profile = options
+            .profile
+            .clone()
+            .or_else(|| std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()));
+
+        let path = app_dir.join(file.file_name().unwrap_or("app.toml".as_ref()));
+        let chain = layers::read_chain(table, layer, &path)?;
+
+        // Merge the chain, most basic file first, then the profile from
+        // all of them
+        let mut table = toml::Table::new();
+        let mut unknown_keys = Vec::new();
+        let mut deprecations = Vec::new();
+        let mut sources = Provenance::default();
+        let mut profiles = Vec::new();
+        for (mut file_table, layer) in chain {
+            let from_layer = |mut diagnostic: Diagnostic| {
+                if layer.source().is_elsewhere() {
+                    diagnostic.message = format!("{} (from {})", diagnostic.message, layer.source());
+                }
+                diagnostic
+            };
+            let migrated = crate::migrate::migrate_table(&mut file_table)
+                .map_err(|e| anyhow::anyhow!("{}: {}", layer.source(), e))?;
+            deprecations.extend(migrated.into_iter().map(from_layer));
+            unknown_keys.extend(crate::keys::unknown_keys(&file_table).into_iter().map(from_layer));
+            let file_profiles = layers::take_profiles(&mut file_table)
+                .map_err(|e| anyhow::anyhow!("{}: {}", layer.source(), e))?;
+            layers::merge_tables(&mut table, file_table, &layer, &mut sources);
+            profiles.push((file_profiles, layer));
+        }
+        layers::apply_profile(&mut table, &profiles, profile.as_deref(), &mut sources)?;
+        apply_layers(&mut table, options, &mut sources)?;
+
+        // Keys from the overrides are only seen now; the files' were
+        // checked above, profiles included
+        for mut unknown in crate::keys::unknown_keys(&table) {
+            if let Some(source) = sources.find_within(&unknown.key).filter(|s| s.is_override()) {
+                unknown.message = format!("{} (from {})", unknown.message, source);
+                unknown_keys.push(unknown);
+            }
+        }
+
+        interpolate_config(&mut table, &app_dir)?;
+
+        let mut config: HarborConfig = toml::Value::Table(table).try_into().map_err(|e| {
+            let mut message = e.to_string().trim_end().to_string();
+            // The error ends with the key; say where its value came from
+            // unless that is the file being loaded
+            let key = message.rsplit_once("in `").and_then(|(_, key)| key.strip_suffix('`'));
+            if let Some(source) = key.and_then(|key| sources.get(key)).filter(|s| s.is_elsewhere()) {
+                message = format!("{} (from {})", message, source);
+            }
+            // A misspelt key often shows up as a missing one
+            for unknown in &unknown_keys {
+                message.push_str(&format!("\n{}", unknown));
+            }
+            anyhow::anyhow!(message)
+        })?;
+        config.unknown_keys = unknown_keys;
+        config.deprecations = deprecations;
+        config.profile = profile;
+        config.sources = sources;
+        config.resolve_paths(app_dir);
+        config.startup_order()?;
         Ok(config)
     }
+
+    /// Expand `~` in path values and make them absolute against `app_dir`
+    ///
+    /// Sockets are resolved earlier, during interpolation, so that
+    /// `${socket}` refers to the final path.
+    fn resolve_paths(&mut self, app_dir: PathBuf) {
+        let resolve = |path: &mut PathBuf| *path = resolve_path(path, &app_dir);
+
+        self.app.icon.iter_mut().for_each(resolve);
+        self.logging.dir.iter_mut().for_each(resolve);
+        let single = Some(&mut self.backend).filter(|b| !b.is_unset());
+        for backend in single.into_iter().chain(self.backends.iter_mut()) {
+            backend.workdir.iter_mut().for_each(resolve);
+            if let Some(ReadinessConfig::File(ref mut file)) = backend.readiness {
+                resolve(&mut file.path);
+            }
+
+            // Bare command names are looked up in PATH
+            if backend.command.contains('/') || backend.command.starts_with('~') {
+                backend.command = resolve_path(Path::new(&backend.command), &app_dir)
+                    .to_string_lossy()
+                    .into_owned();
+            }
+        }
+        self.app_dir = app_dir;
+    }
+
+    /// Get every configured backend: `[backend]` first, then `[[backends]]`
+    pub fn backend_configs(&self) -> Vec<&BackendConfig> {
+        let single = Some(&self.backend).filter(|b| !b.is_unset());
+        single.into_iter().chain(self.backends.iter()).collect()
+    }
+
+    /// Get the backend the frontend talks to
+    ///
+    /// This is the backend whose socket `frontend.url` connects to, or the
+    /// first configured backend with a socket if none matches.
+    pub fn primary_backend(&self) -> Option<&BackendConfig> {
+        let backends = self.backend_configs();
+        backends
+            .iter()
+            .find(|b| self.frontend.connects_to(&b.socket))
+            .or_else(|| backends.iter().find(|b| b.has_socket()))
+            .copied()
+    }
+
+    /// Get the backends in the order they must be started
+    ///
+    /// Every backend comes after the backends it `depends_on`; otherwise the
+    /// declaration order is kept. Fails if there are no backends, names are
+    /// not unique, a dependency is unknown or dependencies form a cycle.
+    pub fn startup_order(&self) -> anyhow::Result<Vec<&BackendConfig>> {
+        let backends = self.backend_configs();
+        if backends.is_empty() {
+            anyhow::bail!("No backend configured: add a [backend] or [[backends]] section");
+        }
+
+        let names: Vec<&str> = backends.iter().map(|b| b.name()).collect();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                anyhow::bail!(
+                    "Duplicate backend name '{}': give each backend a unique `name`",
+                    name
+                );
+            }
+        }
+        for backend in &backends {
+            for dep in &backend.depends_on {
+                if !names.contains(&dep.as_str()) {
+                    anyhow::bail!(
+                        "Backend '{}' depends on unknown backend '{}'",
+                        backend.name(),
+                        dep
+                    );
+                }
+            }
+        }
+
+        // Kahn's algorithm, always picking the earliest declared ready backend
+        let mut order = Vec::with_capacity(backends.len());
+        let mut started = vec![false; backends.len()];
+        while order.len() < backends.len() {
+            let next = (0..backends.len()).find(|&i| {
+                !started[i]
+                    && backends[i].depends_on.iter().all(|dep| {
+                        names
+                            .iter()
+                            .position(|n| n == dep)
+                            .is_some_and(|j| started[j])
+                    })
+            });
+            match next {
+                Some(i) => {
+                    started[i] = true;
+                    order.push(backends[i]);
+                }
+                None => {
+                    let cycle: Vec<&str> = (0..backends.len())
+                        .filter(|&i| !started[i])
+                        .map(|i| names[i])
+                        .collect();
+                    anyhow::bail!(
+                        "Backend dependency cycle between: {}",
+                        cycle.join(", ")
+                    );
+                }
+            }
+        }
+
+        Ok(order)
+    }
 }
 
 /// Application metadata
@@ -56,13 +368,27 @@ pub struct AppConfig {
     pub description: Option<String>,
 }
 
+fn default_config_version() -> i64 {
+    crate::migrate::CONFIG_VERSION
+}
+
 fn default_version() -> String {
     "0.1.0".to_string()
 }
 
 /// Backend server configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// The `Default` is the unset placeholder, with no command, that
+/// `HarborConfig::backend` holds when there is no `[backend]` section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BackendConfig {
+    /// Backend name (defaults to the command's file name)
+    pub name: Option<String>,
+
+    /// Names of backends that must be ready before this one starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+
     /// Command to run the backend (e.g., "gunicorn", "nginx", "python")
     pub command: String,
 
@@ -70,9 +396,18 @@ pub struct BackendConfig {
     #[serde(default)]
     pub args: Vec<String>,
 
-    /// Socket path (Unix) or pipe name (Windows)
+    /// Socket path (Unix), `@name` for a Linux abstract socket, or pipe
+    /// name (Windows); "auto" (the default) picks a path under
+    /// `$XDG_RUNTIME_DIR/harbor/<app>/`, and "none" is for a backend that
+    /// does not listen, such as a worker
+    #[serde(default = "default_socket")]
     pub socket: String,
 
+    /// Bind the socket in Harbor and pass it to the backend using the
+    /// systemd `LISTEN_FDS` protocol, instead of letting the backend bind it
+    #[serde(default)]
+    pub socket_activation: bool,
+
     /// Working directory for the backend process
     pub workdir: Option<PathBuf>,
 
@@ -84,24 +419,357 @@ pub struct BackendConfig {
     #[serde(default = "default_startup_timeout")]
     pub startup_timeout: u64,
 
-    /// Whether to restart on crash
-    #[serde(default = "default_restart")]
-    pub restart_on_crash: bool,
+    /// Deprecated: use `[backend.restart] policy`; `true` means "always",
+    /// `false` means "never"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_on_crash: Option<bool>,
+
+    /// Restart policy, limits and backoff
+    #[serde(default)]
+    pub restart: RestartConfig,
+
+    /// Level at which captured stdout/stderr lines are logged ("off" to disable)
+    #[serde(default = "default_output_level")]
+    pub log_level: String,
+
+    /// Number of recent output lines kept in memory
+    #[serde(default = "default_log_buffer_lines")]
+    pub log_buffer_lines: usize,
+
+    /// Readiness check that must pass (after the socket accepts connections)
+    /// before the backend counts as ready
+    #[serde(default, deserialize_with = "deserialize_readiness")]
+    pub readiness: Option<ReadinessConfig>,
+
+    /// Health monitoring once the backend is running
+    #[serde(default)]
+    pub health: HealthConfig,
+
+    /// Signal asking the backend to stop: "TERM", "INT", "QUIT" or "HUP"
+    #[serde(default)]
+    pub stop_signal: StopSignal,
+
+    /// Seconds to wait for the backend to exit, after the shutdown request
+    /// (if any) and the stop signal together, before killing it
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: u64,
+
+    /// HTTP endpoint sent a POST to ask the backend to shut down, before
+    /// the stop signal (optional)
+    pub shutdown_path: Option<String>,
+}
+
+/// Name of a backend without a `name`: its command's file name
+fn default_backend_name(command: &str) -> &str {
+    std::path::Path::new(command)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(command)
+}
+
+fn default_socket() -> String {
+    AUTO_SOCKET.to_string()
 }
 
 fn default_startup_timeout() -> u64 {
     30
 }
 
-fn default_restart() -> bool {
-    true
+fn default_stop_timeout() -> u64 {
+    10
 }
 
+impl BackendConfig {
+    /// Whether this is the placeholder for a missing `[backend]` section
+    pub fn is_unset(&self) -> bool {
+        self.command.is_empty()
+    }
+
+    /// Whether the backend listens on a socket (`socket` is not "none")
+    pub fn has_socket(&self) -> bool {
+        self.socket != NO_SOCKET
+    }
+
+    /// Get the backend name, defaulting to the command's file name
+    pub fn name(&self) -> &str {
+        match self.name {
+            Some(ref name) => name,
+            None => default_backend_name(&self.command),
+        }
+    }
+
+    /// Get the effective restart policy
+    ///
+    /// An explicit `restart.policy` wins over the legacy `restart_on_crash`.
+    /// With neither, the backend is always restarted, as `restart_on_crash`
+    /// defaulted to `true`.
+    pub fn restart_policy(&self) -> RestartPolicy {
+        match (self.restart.policy, self.restart_on_crash) {
+            (Some(policy), _) => policy,
+            (None, Some(false)) => RestartPolicy::Never,
+            (None, _) => RestartPolicy::Always,
+        }
+    }
+}
+
+/// When a backend that stopped is restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Restart whenever the backend stops, even after a clean exit
+    Always,
+    /// Restart after a non-zero exit, a signal or failed liveness checks
+    OnFailure,
+    /// Never restart
+    Never,
+}
+
+/// Signal sent to stop a backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum StopSignal {
+    /// SIGTERM, the usual request to terminate
+    #[default]
+    Term,
+    /// SIGINT, as sent by Ctrl-C
+    Int,
+    /// SIGQUIT, which some servers (nginx) treat as a graceful shutdown
+    Quit,
+    /// SIGHUP
+    Hup,
+}
+
+impl std::fmt::Display for StopSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            StopSignal::Term => "SIGTERM",
+            StopSignal::Int => "SIGINT",
+            StopSignal::Quit => "SIGQUIT",
+            StopSignal::Hup => "SIGHUP",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Restart configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartConfig {
+    /// Restart policy (default: "always")
+    pub policy: Option<RestartPolicy>,
+
+    /// Restarts allowed within `window_secs` before giving up (0 = unlimited)
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+
+    /// Window for counting restarts, in seconds
+    #[serde(default = "default_restart_window")]
+    pub window_secs: u64,
+
+    /// Delay before the first restart in milliseconds; doubles per restart
+    #[serde(default = "default_backoff_initial")]
+    pub backoff_initial_ms: u64,
+
+    /// Upper bound for the restart delay in milliseconds
+    #[serde(default = "default_backoff_max")]
+    pub backoff_max_ms: u64,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            policy: None,
+            max_restarts: default_max_restarts(),
+            window_secs: default_restart_window(),
+            backoff_initial_ms: default_backoff_initial(),
+            backoff_max_ms: default_backoff_max(),
+        }
+    }
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_restart_window() -> u64 {
+    60
+}
+
+fn default_backoff_initial() -> u64 {
+    500
+}
+
+fn default_backoff_max() -> u64 {
+    30_000
+}
+
+fn default_output_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_buffer_lines() -> usize {
+    1000
+}
+
+/// Readiness strategy configuration
+///
+/// Selected with `type` in `[backend.readiness]`; a table without `type`
+/// is an HTTP probe.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ReadinessConfig {
+    /// Send an HTTP request over the socket
+    Http(HttpReadinessConfig),
+
+    /// Wait for a line of backend output matching a regex
+    LogLine(LogLineReadinessConfig),
+
+    /// Wait for the backend to create a marker file
+    File(FileReadinessConfig),
+
+    /// Wait for the backend to send `READY=1` on `$NOTIFY_SOCKET`
+    Notify(NotifyReadinessConfig),
+}
+
+/// Treat a `[backend.readiness]` table without `type` as an HTTP probe
+fn deserialize_readiness<'de, D>(deserializer: D) -> Result<Option<ReadinessConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let Some(mut table) = Option::<toml::Table>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    table
+        .entry("type")
+        .or_insert_with(|| toml::Value::String("http".to_string()));
+    toml::Value::Table(table)
+        .try_into()
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// HTTP readiness probe configuration
+///
+/// Once the socket accepts connections, Harbor sends this request over it
+/// until the response matches before reporting the backend as started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpReadinessConfig {
+    /// HTTP method
+    #[serde(default = "default_probe_method")]
+    pub method: String,
+
+    /// Request path
+    #[serde(default = "default_probe_path")]
+    pub path: String,
+
+    /// Expected response status
+    #[serde(default = "default_probe_status")]
+    pub status: u16,
+
+    /// Text the response body must contain (optional)
+    pub body_contains: Option<String>,
+
+    /// Delay between attempts in milliseconds
+    #[serde(default = "default_probe_interval")]
+    pub interval_ms: u64,
+}
+
+fn default_probe_method() -> String {
+    "GET".to_string()
+}
+
+fn default_probe_path() -> String {
+    "/".to_string()
+}
+
+fn default_probe_status() -> u16 {
+    200
+}
+
+fn default_probe_interval() -> u64 {
+    250
+}
+
+/// Health monitoring configuration
+///
+/// The process is always checked for liveness; setting `path` also sends
+/// an HTTP request over the socket on every check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Delay between checks in milliseconds
+    #[serde(default = "default_health_interval")]
+    pub interval_ms: u64,
+
+    /// HTTP liveness endpoint (optional)
+    pub path: Option<String>,
+
+    /// HTTP method for the liveness request
+    #[serde(default = "default_probe_method")]
+    pub method: String,
+
+    /// Expected liveness response status
+    #[serde(default = "default_probe_status")]
+    pub status: u16,
+
+    /// Liveness request timeout in milliseconds
+    #[serde(default = "default_health_timeout")]
+    pub timeout_ms: u64,
+
+    /// Consecutive liveness failures before the backend is restarted
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: default_health_interval(),
+            path: None,
+            method: default_probe_method(),
+            status: default_probe_status(),
+            timeout_ms: default_health_timeout(),
+            failure_threshold: default_failure_threshold(),
+        }
+    }
+}
+
+fn default_health_interval() -> u64 {
+    1000
+}
+
+fn default_health_timeout() -> u64 {
+    2000
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+/// Log-line readiness configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLineReadinessConfig {
+    /// Regular expression matched against each stdout/stderr line
+    pub pattern: String,
+}
+
+/// Marker-file readiness configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileReadinessConfig {
+    /// File the backend creates once it is ready (removed before start)
+    pub path: PathBuf,
+}
+
+/// sd_notify readiness configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotifyReadinessConfig {}
+
 /// Frontend window configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FrontendConfig {
     /// URL to load (transport-aware URL)
-    /// Example: "http::unix///tmp/app.sock/" or "http::pipe//myapp/"
+    /// Example: "http::unix///tmp/app.sock/", "http::unix//@myapp/" or
+    /// "http::pipe//myapp/"
     pub url: String,
 
     /// Window width
@@ -134,6 +802,25 @@ pub struct FrontendConfig {
     pub max_size: Option<(u32, u32)>,
 }
 
+impl FrontendConfig {
+    /// Whether `url` connects to the backend listening on `socket`
+    ///
+    /// The URL is the transport (`http::unix//` or `http::pipe//`), then
+    /// the socket, then the path, which starts with `/`.
+    pub fn connects_to(&self, socket: &str) -> bool {
+        let Some(rest) = ["http::unix//", "http::pipe//"]
+            .iter()
+            .find_map(|transport| self.url.strip_prefix(transport))
+        else {
+            return false;
+        };
+        !socket.is_empty()
+            && rest
+                .strip_prefix(socket)
+                .is_some_and(|path| path.is_empty() || path.starts_with('/'))
+    }
+}
+
 fn default_width() -> u32 {
     1024
 }
@@ -151,13 +838,13 @@ fn default_decorated() -> bool {
 }
 
 /// Additional settings
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SettingsConfig {
     /// Enable developer tools
     #[serde(default)]
     pub devtools: bool,
 
-    /// Log level (trace, debug, info, warn, error)
+    /// Log level (off, trace, debug, info, warn, error)
     #[serde(default = "default_log_level")]
     pub log_level: String,
 
@@ -165,10 +852,313 @@ pub struct SettingsConfig {
     pub user_agent: Option<String>,
 }
 
+impl Default for SettingsConfig {
+    fn default() -> Self {
+        Self {
+            devtools: false,
+            log_level: default_log_level(),
+            user_agent: None,
+        }
+    }
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+/// Log file configuration
+///
+/// Backend output and Harbor's own logs are written to `backend.log` and
+/// `harbor.log` in a per-app directory, by default
+/// `$XDG_STATE_HOME/harbor/<app>/` (`~/.local/state/harbor/<app>/`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Whether to write log files
+    #[serde(default = "default_logging_enabled")]
+    pub enabled: bool,
+
+    /// Directory for this app's log files (overrides the XDG state directory)
+    pub dir: Option<PathBuf>,
+
+    /// Rotate when a file exceeds this size in megabytes (0 disables)
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+
+    /// Rotate when a file is older than this many hours
+    pub max_age_hours: Option<u64>,
+
+    /// Number of rotated files to keep
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_logging_enabled(),
+            dir: None,
+            max_size_mb: default_max_size_mb(),
+            max_age_hours: None,
+            keep: default_keep(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Get the log directory for an app, or `None` if file logging is disabled
+    /// or no state directory can be determined
+    pub fn dir_for(&self, app_name: &str) -> Option<PathBuf> {
+        if !self.enabled {
+            return None;
+        }
+        if let Some(ref dir) = self.dir {
+            return Some(dir.clone());
+        }
+
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })?;
+
+        Some(state_home.join("harbor").join(app_dir_name(app_name)))
+    }
+
+    /// Get the rotation policy for log files
+    pub fn rotation(&self) -> RotationPolicy {
+        RotationPolicy {
+            max_size: self.max_size_mb.saturating_mul(1024 * 1024),
+            max_age: self
+                .max_age_hours
+                .map(|h| Duration::from_secs(h.saturating_mul(3600))),
+            keep: self.keep,
+        }
+    }
+}
+
+/// Directory name used for per-app files
+///
+/// Path separators become `-` too, so that a name such as `@scope/pkg` is
+/// one directory.
+pub fn app_dir_name(app_name: &str) -> String {
+    app_name.to_lowercase().replace([' ', '/', '\\'], "-")
+}
+
+/// Expand a leading `~` to the home directory and make a relative path
+/// absolute against `base`
+///
+/// `.` components and trailing slashes are dropped; `..` is kept.
+pub fn resolve_path(path: &Path, base: &Path) -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let path = match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    };
+    base.join(path).components().collect()
+}
+
+/// Resolve a socket file path like other paths; abstract sockets and
+/// Windows pipe names are left alone
+fn resolve_socket(socket: &str, app_dir: &Path) -> String {
+    if cfg!(windows) || socket.starts_with('@') {
+        return socket.to_string();
+    }
+    resolve_path(Path::new(socket), app_dir)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Data directory for an app: `$XDG_DATA_HOME/harbor/<app>/`, or
+/// `~/.local/share/harbor/<app>/`
+///
+/// This is what `${data_dir}` refers to. It is not created by Harbor.
+pub fn data_dir_for(app_name: &str) -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+
+    Some(data_home.join("harbor").join(app_dir_name(app_name)))
+}
+
+/// Socket path for an `auto` backend socket
+///
+/// The path is unique to this Harbor process, so two instances of an app
+/// (or two apps) never collide: `$XDG_RUNTIME_DIR/harbor/<app>/<backend>-<pid>.sock`,
+/// or a per-user directory under the system temp dir if `XDG_RUNTIME_DIR`
+/// is not set.
+pub fn auto_socket_path(app_name: &str, backend_name: &str) -> PathBuf {
+    socket_root()
+        .join(app_dir_name(app_name))
+        .join(format!("{}-{}.sock", backend_name, std::process::id()))
+}
+
+/// Path of the `NOTIFY_SOCKET` for a backend with sd_notify readiness,
+/// next to its `auto` socket
+pub fn notify_socket_path(app_name: &str, backend_name: &str) -> PathBuf {
+    socket_root()
+        .join(app_dir_name(app_name))
+        .join(format!("{}-{}.notify", backend_name, std::process::id()))
+}
+
+/// Apply the layers above the config file: the user's override file,
+/// environment variables and `--set` assignments
+fn apply_layers(table: &mut toml::Table, options: &LoadOptions, sources: &mut Provenance) -> anyhow::Result<()> {
+    if options.user_config {
+        let app_name = table
+            .get("app")
+            .and_then(|app| app.get("name"))
+            .and_then(toml::Value::as_str)
+            .unwrap_or_default();
+        if let Some(path) = layers::user_config_path(app_name).filter(|p| p.exists()) {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let overlay: toml::Table = toml::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            let user = Layer::from_file(Source::UserFile { path, line: None }, &contents);
+            layers::merge_tables(table, overlay, &user, sources);
+        }
+    }
+
+    if options.env {
+        let mut vars: Vec<(String, String)> = std::env::vars().collect();
+        vars.sort();
+        for (var, value) in vars {
+            if let Some(path) = layers::env_key_path(&var) {
+                layers::set_value(table, &path, &value, &Layer::new(Source::Env(var)), sources)?;
+            }
+        }
+    }
+
+    for assignment in &options.sets {
+        let (path, value) = layers::parse_assignment(assignment)?;
+        let set = Layer::new(Source::Set(assignment.clone()));
+        layers::set_value(table, &path, value, &set, sources)?;
+    }
+    Ok(())
+}
+
+/// Backend keys resolved before the rest of the backend, which may refer
+/// to its `${socket}`
+const BACKEND_IDENTITY_KEYS: [&str; 3] = ["name", "command", "socket"];
+
+/// Expand `${...}` references throughout a parsed app.toml
+///
+/// `app_dir` is the value of `${app_dir}`, against which socket paths are
+/// resolved.
+///
+/// `auto` sockets are allocated along the way so that `${socket}` can refer
+/// to them: within a backend it is that backend's socket, elsewhere the
+/// first backend's.
+fn interpolate_config(table: &mut toml::Table, app_dir: &Path) -> anyhow::Result<()> {
+    let mut vars = Variables {
+        app_dir: Some(app_dir.to_path_buf()),
+        ..Variables::default()
+    };
+
+    // The app name can use any variable except those derived from it
+    if let Some(name) = table.get_mut("app").and_then(|app| app.get_mut("name")) {
+        interpolate_value(name, "app.name", &vars)?;
+        vars.app_name = name.as_str().map(str::to_string);
+    }
+    vars.data_dir = vars.app_name.as_deref().and_then(data_dir_for);
+    let app_name = vars.app_name.clone().unwrap_or_default();
+
+    let mut backends = Vec::new();
+    let mut rest = Vec::new();
+    for (section, value) in table.iter_mut() {
+        match (section.as_str(), value) {
+            ("backend", toml::Value::Table(backend)) => backends.push((section.clone(), backend)),
+            ("backends", toml::Value::Array(items)) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    if let toml::Value::Table(backend) = item {
+                        backends.push((format!("backends[{}]", i), backend));
+                    }
+                }
+            }
+            ("app", toml::Value::Table(app)) => {
+                for (key, value) in app.iter_mut().filter(|(key, _)| *key != "name") {
+                    rest.push((join_key("app", key), value));
+                }
+            }
+            (_, value) => rest.push((section.clone(), value)),
+        }
+    }
+
+    for (key, backend) in backends {
+        for field in BACKEND_IDENTITY_KEYS {
+            if let Some(value) = backend.get_mut(field) {
+                interpolate_value(value, &join_key(&key, field), &vars)?;
+            }
+        }
+
+        let socket = match backend.get("socket").and_then(toml::Value::as_str) {
+            Some(NO_SOCKET) => NO_SOCKET.to_string(),
+            Some(socket) if !socket.is_empty() && socket != AUTO_SOCKET => {
+                resolve_socket(socket, app_dir)
+            }
+            _ => {
+                let command = backend.get("command").and_then(toml::Value::as_str);
+                let name = backend
+                    .get("name")
+                    .and_then(toml::Value::as_str)
+                    .or(command.map(default_backend_name))
+                    .unwrap_or("backend");
+                auto_socket_path(&app_name, name).to_string_lossy().into_owned()
+            }
+        };
+        backend.insert("socket".to_string(), toml::Value::String(socket.clone()));
+
+        // A backend without a socket has no `${socket}`
+        let socket = Some(socket).filter(|s| s != NO_SOCKET);
+        let backend_vars = Variables {
+            socket: socket.clone(),
+            ..vars.clone()
+        };
+        for (field, value) in backend.iter_mut() {
+            if !BACKEND_IDENTITY_KEYS.contains(&field.as_str()) {
+                interpolate_value(value, &join_key(&key, field), &backend_vars)?;
+            }
+        }
+        if vars.socket.is_none() {
+            vars.socket = socket;
+        }
+    }
+
+    for (key, value) in rest {
+        interpolate_value(value, &key, &vars)?;
+    }
+    Ok(())
+}
+
+fn socket_root() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+    {
+        return dir.join("harbor");
+    }
+
+    #[cfg(unix)]
+    let dir = format!("harbor-{}", nix::unistd::geteuid());
+    #[cfg(not(unix))]
+    let dir = "harbor".to_string();
+    std::env::temp_dir().join(dir)
+}
+
+fn default_logging_enabled() -> bool {
+    true
+}
+
+fn default_max_size_mb() -> u64 {
+    10
+}
+
+fn default_keep() -> usize {
+    5
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,9 +1181,9 @@ mod tests {
             height = 800
         "#;
 
-        let config = HarborConfig::from_str(toml).unwrap();
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
         assert_eq!(config.app.name, "Test App");
-        assert_eq!(config.backend.command, "gunicorn");
+        assert_eq!(&config.backend.command, "gunicorn");
         assert_eq!(config.frontend.width, 1200);
     }
 
@@ -212,10 +1202,482 @@ mod tests {
             url = "http::unix///tmp/minimal.sock/"
         "#;
 
-        let config = HarborConfig::from_str(toml).unwrap();
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
         assert_eq!(config.app.version, "0.1.0");
         assert_eq!(config.frontend.width, 1024);
         assert_eq!(config.frontend.height, 768);
         assert!(config.frontend.resizable);
+        assert!(config.logging.enabled);
+        assert_eq!(config.logging.keep, 5);
+    }
+
+    #[test]
+    fn test_readiness_probe() {
+        let toml = r#"
+            [app]
+            name = "Probe App"
+
+            [backend]
+            command = "gunicorn"
+            socket = "/tmp/probe.sock"
+
+            [backend.readiness]
+            path = "/healthz"
+            body_contains = "ok"
+
+            [frontend]
+            url = "http::unix///tmp/probe.sock/"
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
+        let Some(ReadinessConfig::Http(readiness)) = config.backend.readiness else {
+            panic!("expected an HTTP readiness probe");
+        };
+        assert_eq!(readiness.method, "GET");
+        assert_eq!(readiness.path, "/healthz");
+        assert_eq!(readiness.status, 200);
+        assert_eq!(readiness.body_contains.as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn test_readiness_strategies() {
+        let parse = |readiness: &str| {
+            let toml = format!(
+                r#"
+                [app]
+                name = "Strategy App"
+
+                [backend]
+                command = "node"
+                socket = "/tmp/strategy.sock"
+                readiness = {}
+
+                [frontend]
+                url = "http::unix///tmp/strategy.sock/"
+                "#,
+                readiness
+            );
+            HarborConfig::from_str(&toml, "/srv/app").unwrap().backend.readiness
+        };
+
+        assert!(matches!(
+            parse(r#"{ type = "log-line", pattern = "Listening at" }"#),
+            Some(ReadinessConfig::LogLine(c)) if c.pattern == "Listening at"
+        ));
+        assert!(matches!(
+            parse(r#"{ type = "file", path = "/tmp/strategy.ready" }"#),
+            Some(ReadinessConfig::File(c)) if c.path.to_str() == Some("/tmp/strategy.ready")
+        ));
+        assert!(matches!(
+            parse(r#"{ type = "notify" }"#),
+            Some(ReadinessConfig::Notify(_))
+        ));
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let parse = |backend_extra: &str| {
+            let toml = format!(
+                r#"
+                [app]
+                name = "Restart App"
+
+                [backend]
+                command = "uvicorn"
+                socket = "/tmp/restart.sock"
+                {}
+
+                [frontend]
+                url = "http::unix///tmp/restart.sock/"
+                "#,
+                backend_extra
+            );
+            HarborConfig::from_str(&toml, "/srv/app").unwrap().backend
+        };
+
+        assert_eq!(parse("").restart_policy(), RestartPolicy::Always);
+        assert_eq!(parse("restart_on_crash = true").restart_policy(), RestartPolicy::Always);
+        assert_eq!(parse("restart_on_crash = false").restart_policy(), RestartPolicy::Never);
+
+        let backend = parse(
+            r#"
+            restart_on_crash = true
+            restart = { policy = "never", max_restarts = 2, window_secs = 30 }
+            "#,
+        );
+        assert_eq!(backend.restart_policy(), RestartPolicy::Never);
+        assert_eq!(backend.restart.max_restarts, 2);
+        assert_eq!(backend.restart.window_secs, 30);
+        assert_eq!(backend.restart.backoff_max_ms, 30_000);
+    }
+
+    #[test]
+    fn test_multiple_backends() {
+        let toml = r#"
+            [app]
+            name = "Stack"
+
+            [[backends]]
+            name = "nginx"
+            command = "nginx"
+            socket = "/tmp/stack-nginx.sock"
+            depends_on = ["api"]
+
+            [[backends]]
+            name = "worker"
+            command = "celery"
+            socket = "/tmp/stack-worker.sock"
+            depends_on = ["api"]
+
+            [[backends]]
+            name = "api"
+            command = "gunicorn"
+            socket = "/tmp/stack-api.sock"
+
+            [frontend]
+            url = "http::unix///tmp/stack-nginx.sock/"
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
+        let order: Vec<&str> = config.startup_order().unwrap().iter().map(|b| b.name()).collect();
+        assert_eq!(order, ["api", "nginx", "worker"]);
+        assert_eq!(config.primary_backend().unwrap().name(), "nginx");
+
+        // The socket must match exactly, not just be part of the URL
+        let toml = toml.replace("/tmp/stack-nginx.sock/", "/tmp/stack-api.sock.old/");
+        let config = HarborConfig::from_str(&toml, "/srv/app").unwrap();
+        assert!(!config.frontend.connects_to("/tmp/stack-api.sock"));
+        assert!(config.frontend.connects_to("/tmp/stack-api.sock.old"));
+        assert_eq!(config.primary_backend().unwrap().name(), "nginx");
+    }
+
+    #[test]
+    fn test_backend_dependency_errors() {
+        let parse = |backends: &str| {
+            let toml = format!(
+                r#"
+                [app]
+                name = "Broken"
+                {}
+                [frontend]
+                url = "http::unix///tmp/broken.sock/"
+                "#,
+                backends
+            );
+            HarborConfig::from_str(&toml, "/srv/app").unwrap_err().to_string()
+        };
+
+        assert!(parse("").contains("No backend configured"));
+        assert!(parse(
+            r#"
+            [[backends]]
+            command = "gunicorn"
+            socket = "/tmp/a.sock"
+            [[backends]]
+            command = "gunicorn"
+            socket = "/tmp/b.sock"
+            "#
+        )
+        .contains("Duplicate backend name 'gunicorn'"));
+        assert!(parse(
+            r#"
+            [[backends]]
+            name = "a"
+            command = "a"
+            socket = "/tmp/a.sock"
+            depends_on = ["b"]
+            [[backends]]
+            name = "b"
+            command = "b"
+            socket = "/tmp/b.sock"
+            depends_on = ["a"]
+            "#
+        )
+        .contains("cycle between: a, b"));
+    }
+
+    #[test]
+    fn test_logging_section() {
+        let toml = r#"
+            [app]
+            name = "My App"
+
+            [backend]
+            command = "python"
+            socket = "/tmp/logging.sock"
+
+            [frontend]
+            url = "http::unix///tmp/logging.sock/"
+
+            [logging]
+            dir = "/var/tmp/my-app-logs"
+            max_size_mb = 2
+            max_age_hours = 24
+            keep = 3
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
+        let policy = config.logging.rotation();
+        assert_eq!(policy.max_size, 2 * 1024 * 1024);
+        assert_eq!(policy.max_age, Some(Duration::from_secs(86_400)));
+        assert_eq!(policy.keep, 3);
+        assert_eq!(
+            config.logging.dir_for(&config.app.name),
+            Some(PathBuf::from("/var/tmp/my-app-logs"))
+        );
+        assert_eq!(app_dir_name("My App"), "my-app");
+        assert_eq!(app_dir_name("@acme/Reports"), "@acme-reports");
+    }
+
+    #[test]
+    fn test_auto_socket() {
+        let toml = r#"
+            [app]
+            name = "Auto App"
+
+            [[backends]]
+            name = "api"
+            command = "gunicorn"
+            args = ["--bind", "unix:${socket}", "app:app"]
+            env = { API_SOCKET = "${socket}" }
+
+            [[backends]]
+            name = "worker"
+            command = "worker"
+            socket = "auto"
+
+            [frontend]
+            url = "http::unix//${socket}/"
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/app").unwrap();
+        let api = &config.backends[0];
+        let suffix = format!("/auto-app/api-{}.sock", std::process::id());
+        assert!(api.socket.ends_with(&suffix), "{}", api.socket);
+        assert_eq!(api.args[1], format!("unix:{}", api.socket));
+        assert_eq!(api.env["API_SOCKET"], api.socket);
+        assert_ne!(config.backends[1].socket, api.socket);
+        assert_eq!(config.frontend.url, format!("http::unix//{}/", api.socket));
+        assert_eq!(config.primary_backend().unwrap().name(), "api");
+
+        // A backend without a socket has no `${socket}`, and the frontend's
+        // is the next backend's
+        let toml = toml.replace(
+            "[[backends]]\n            name = \"api\"",
+            "[[backends]]\n            name = \"jobs\"\n            command = \"jobs\"\n            socket = \"none\"\n\n            [[backends]]\n            name = \"api\"",
+        );
+        let config = HarborConfig::from_str(&toml, "/srv/app").unwrap();
+        assert_eq!(config.backends[0].socket, "none");
+        assert!(!config.backends[0].has_socket());
+        assert_eq!(config.frontend.url, format!("http::unix//{}/", config.backends[1].socket));
+        assert_eq!(config.primary_backend().unwrap().name(), "api");
+
+        let toml = toml.replace("command = \"jobs\"", "command = \"jobs\"\n            args = [\"${socket}\"]");
+        let err = HarborConfig::from_str(&toml, "/srv/app").unwrap_err();
+        assert_eq!(err.to_string(), "backends[0].args[0]: ${socket} is not available here");
+    }
+
+    #[test]
+    fn test_interpolation() {
+        std::env::set_var("HARBOR_TEST_CONFIG_PORT", "8080");
+        let toml = r#"
+            [app]
+            name = "${env:HARBOR_TEST_CONFIG_APP:-Interp App}"
+
+            [backend]
+            command = "${app_dir}/venv/bin/python"
+            args = ["${app.name}", "--port", "${env:HARBOR_TEST_CONFIG_PORT}"]
+            socket = "/tmp/${app.name}.sock"
+            env = { CACHE = "${data_dir}/cache", LITERAL = "$${socket}" }
+
+            [frontend]
+            url = "http::unix//${socket}/"
+            title = "${app.name}"
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/interp").unwrap();
+        assert_eq!(config.app.name, "Interp App");
+        assert_eq!(config.frontend.title.as_deref(), Some("Interp App"));
+        assert_eq!(config.frontend.url, "http::unix///tmp/Interp App.sock/");
+        let backend = config.backend;
+        assert_eq!(backend.command, "/srv/interp/venv/bin/python");
+        assert_eq!(backend.args, vec!["Interp App", "--port", "8080"]);
+        assert_eq!(backend.socket, "/tmp/Interp App.sock");
+        assert!(backend.env["CACHE"].ends_with("/harbor/interp-app/cache"));
+        assert_eq!(backend.env["LITERAL"], "${socket}");
+
+        let err = HarborConfig::from_str(
+            r#"
+            [app]
+            name = "x"
+
+            [[backends]]
+            command = "app"
+            env = { TOKEN = "${env:HARBOR_TEST_CONFIG_UNSET}" }
+
+            [frontend]
+            url = "http::unix//${socket}/"
+            "#,
+            "/srv/app",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().starts_with("backends[0].env.TOKEN: environment variable HARBOR_TEST_CONFIG_UNSET"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let toml = r#"
+            [app]
+            name = "Paths"
+            icon = "~/icons/paths.png"
+
+            [[backends]]
+            name = "web"
+            command = "./venv/bin/gunicorn"
+            workdir = "."
+            socket = "run/web.sock"
+            args = ["--bind", "unix:${socket}"]
+
+            [[backends]]
+            name = "cache"
+            command = "redis-server"
+            workdir = "../shared"
+            socket = "@paths-cache"
+
+            [backends.readiness]
+            type = "file"
+            path = "run/cache.ready"
+
+            [frontend]
+            url = "http::unix//${socket}/"
+        "#;
+
+        let config = HarborConfig::from_str(toml, "/srv/paths/").unwrap();
+        assert_eq!(config.app_dir, PathBuf::from("/srv/paths"));
+        if !home.is_empty() {
+            assert_eq!(config.app.icon, Some(Path::new(&home).join("icons/paths.png")));
+        }
+
+        let web = &config.backends[0];
+        assert_eq!(web.command, "/srv/paths/venv/bin/gunicorn");
+        assert_eq!(web.workdir, Some(PathBuf::from("/srv/paths")));
+        assert_eq!(web.socket, "/srv/paths/run/web.sock");
+        assert_eq!(web.args[1], "unix:/srv/paths/run/web.sock");
+        assert_eq!(config.frontend.url, "http::unix///srv/paths/run/web.sock/");
+
+        let cache = &config.backends[1];
+        assert_eq!(cache.command, "redis-server");
+        assert_eq!(cache.workdir, Some(PathBuf::from("/srv/paths/../shared")));
+        assert_eq!(cache.socket, "@paths-cache");
+        let Some(ReadinessConfig::File(ref ready)) = cache.readiness else {
+            panic!("expected a file readiness check");
+        };
+        assert_eq!(ready.path, PathBuf::from("/srv/paths/run/cache.ready"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let toml = r#"
+            [app]
+            name = "Profiles"
+
+            [[backends]]
+            name = "web"
+            command = "gunicorn"
+            args = ["--workers", "4", "app:app"]
+            socket = "/tmp/profiles-web.sock"
+
+            [[backends]]
+            name = "worker"
+            command = "worker"
+            socket = "/tmp/profiles-worker.sock"
+
+            [frontend]
+            url = "http::unix///tmp/profiles-web.sock/"
+
+            [profile.dev.settings]
+            devtools = true
+
+            [[profile.dev.backends]]
+            name = "web"
+            args = ["--reload", "app:app"]
+
+            [[profile.dev.backends]]
+            name = "mock-api"
+            command = "mock-api"
+            socket = "/tmp/profiles-mock.sock"
+        "#;
+        let load = |profile: &str| {
+            HarborConfig::from_str_with(toml, "/srv/app", &LoadOptions::default().with_profile(profile))
+        };
+
+        let dev = load("dev").unwrap();
+        assert_eq!(dev.profile.as_deref(), Some("dev"));
+        assert!(dev.settings.devtools);
+        let names: Vec<&str> = dev.backends.iter().map(|b| b.name()).collect();
+        assert_eq!(names, vec!["web", "worker", "mock-api"]);
+        assert_eq!(dev.backends[0].command, "gunicorn");
+        assert_eq!(dev.backends[0].args, vec!["--reload", "app:app"]);
+        assert!(dev.unknown_keys.is_empty());
+
+        let err = load("release").unwrap_err().to_string();
+        assert_eq!(err, "Unknown profile 'release' (available: dev)");
+    }
+
+    #[test]
+    fn test_extends() {
+        let dir = std::env::temp_dir().join(format!("harbor-test-extends-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        std::fs::write(
+            dir.join("shared/runtime.toml"),
+            "[backend]\ncommand = \"gunicorn\"\nstartup_timeout = 60\n\n[profile.dev.settings]\ndevtools = true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared/base.toml"),
+            "extends = \"runtime.toml\"\n\n[backend]\nlog_level = \"debug\"\nworkdir = \"missing\"\n",
+        )
+        .unwrap();
+        let toml = r#"
+            extends = "shared/base.toml"
+
+            [app]
+            name = "Extends"
+
+            [backend]
+            command = "sh"
+            socket = "/tmp/extends.sock"
+
+            [frontend]
+            url = "http::unix///tmp/extends.sock/"
+        "#;
+
+        let options = LoadOptions::default().with_profile("dev");
+        let config = HarborConfig::from_str_with(toml, &dir, &options).unwrap();
+        let backend = &config.backend;
+        assert_eq!(backend.command, "sh");
+        assert_eq!(backend.startup_timeout, 60);
+        assert_eq!(backend.log_level, "debug");
+        assert!(config.settings.devtools);
+        let base = dir.join("shared/base.toml");
+        assert_eq!(
+            config.sources.source_of("backend.log_level").to_string(),
+            format!("{}:4", base.display())
+        );
+        let workdir = config.validate().into_iter().find(|d| d.key == "backend.workdir").unwrap();
+        assert!(workdir.message.ends_with(&format!("(from {}:5)", base.display())));
+
+        std::fs::write(dir.join("shared/runtime.toml"), "extends = \"../app.toml\"\n").unwrap();
+        std::fs::write(dir.join("app.toml"), toml).unwrap();
+        let err = HarborConfig::load(dir.join("app.toml")).unwrap_err().to_string();
+        assert!(err.contains("extends cycle"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,332 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Changing settings in a config file
+//!
+//! `harbor config set` and `harbor config unset` change one key of app.toml
+//! (or `[tool.harbor]` in a pyproject.toml) and leave the rest of the file,
+//! comments and formatting included, as it was. Values are checked against
+//! the JSON Schema as they are set, and the whole edited file must still
+//! load and validate before it is written.
+
+use crate::config::{HarborConfig, LoadOptions};
+use crate::layers::{parse_key_path, parse_value, Segment};
+use crate::manifest::PYPROJECT_TOML;
+use crate::schema::Checker;
+use anyhow::{anyhow, bail, Context};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+/// An open config file being edited
+pub struct ConfigEditor {
+    path: PathBuf,
+    document: DocumentMut,
+    checker: Checker,
+    /// Errors the file had before it was edited
+    errors: Vec<String>,
+}
+
+impl ConfigEditor {
+    /// Open the config file at `path` for editing
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read: {}", path.display()))?;
+        Self::parse(path, &contents)
+    }
+
+    /// Edit `contents`, the config file at `path`
+    pub fn parse(path: &Path, contents: &str) -> anyhow::Result<Self> {
+        if path.extension().is_some_and(|ext| ext == "json") {
+            bail!("{}: only TOML files can be edited", path.display());
+        }
+        let mut editor = ConfigEditor {
+            path: path.to_path_buf(),
+            document: contents.parse()?,
+            checker: Checker::default(),
+            errors: Vec::new(),
+        };
+        if editor.is_pyproject() && editor.document.get("tool").and_then(|t| t.get("harbor")).is_none() {
+            bail!("{} has no [tool.harbor] table", path.display());
+        }
+        editor.errors = editor.errors();
+        Ok(editor)
+    }
+
+    /// Set `key`, a key path such as `frontend.width`, to `raw`
+    ///
+    /// `raw` is read as a TOML value, except where the schema expects a
+    /// string, so `app.version 2.0` is the string "2.0". Anything else that
+    /// is not valid TOML is taken as a string.
+    pub fn set(&mut self, key: &str, raw: &str) -> anyhow::Result<()> {
+        let path = parse_key_path(key)?;
+        let (schema, _) = self.checker.lookup(&path)?;
+
+        let wants_string = schema.get("type").and_then(toml::Value::as_str) == Some("string");
+        let parsed = parse_value(raw);
+        let value = match parsed {
+            toml::Value::String(_) => Value::from(parsed.as_str().unwrap_or_default()),
+            _ if wants_string => Value::from(raw),
+            _ => raw.parse::<Value>()?,
+        };
+        let checked = if wants_string && !parsed.is_str() {
+            toml::Value::String(raw.to_string())
+        } else {
+            parsed
+        };
+        self.checker.check(&schema, &checked, key)?;
+
+        set_in(self.root_mut()?, &path, value, false, key)
+    }
+
+    /// Remove `key` from the file, so that its default applies
+    ///
+    /// Required keys cannot be removed, except from a profile.
+    pub fn unset(&mut self, key: &str) -> anyhow::Result<()> {
+        let path = parse_key_path(key)?;
+        let (_, parent) = self.checker.lookup(&path)?;
+
+        let in_profile = path.first() == Some(&Segment::Key("profile".to_string()));
+        if let (Some(Segment::Key(name)), false) = (path.last(), in_profile) {
+            let required = parent.get("required").and_then(toml::Value::as_array);
+            if required.into_iter().flatten().any(|r| r.as_str() == Some(name)) {
+                bail!("{}: required, so it cannot be unset", key);
+            }
+        }
+
+        let file = self.path.display().to_string();
+        if !unset_in(self.root_mut()?, &path) {
+            bail!("{}: not set in {}", key, file);
+        }
+        Ok(())
+    }
+
+    /// The edited file
+    pub fn contents(&self) -> String {
+        self.document.to_string()
+    }
+
+    /// Check that the edited file still loads and validates
+    ///
+    /// Only new errors count, so that a file that was already broken (say,
+    /// its backend command is not installed here) can still be edited.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let new: Vec<String> = self.errors().into_iter().filter(|e| !self.errors.contains(e)).collect();
+        if !new.is_empty() {
+            bail!(new.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Write the edited file back, if it passes `check`
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.check()?;
+        std::fs::write(&self.path, self.contents())
+            .with_context(|| format!("Failed to write: {}", self.path.display()))
+    }
+
+    /// Errors loading and validating the file as edited
+    fn errors(&self) -> Vec<String> {
+        let contents = self.contents();
+        let app_dir = self.path.parent().unwrap_or(Path::new(""));
+        let config = crate::manifest::read(&self.path, &contents)
+            .and_then(|(table, layer)| HarborConfig::parse(table, layer, app_dir, &self.path, &LoadOptions::default()));
+        match config {
+            Ok(config) => config
+                .validate()
+                .iter()
+                .filter(|d| d.is_error())
+                .map(ToString::to_string)
+                .collect(),
+            Err(e) => vec![format!("{:#}", e)],
+        }
+    }
+
+    fn is_pyproject(&self) -> bool {
+        self.path.file_name().is_some_and(|name| name == PYPROJECT_TOML)
+    }
+
+    /// The table holding the Harbor config
+    fn root_mut(&mut self) -> anyhow::Result<&mut dyn TableLike> {
+        if !self.is_pyproject() {
+            return Ok(self.document.as_table_mut());
+        }
+        let path = self.path.display().to_string();
+        self.document
+            .get_mut("tool")
+            .and_then(|tool| tool.get_mut("harbor"))
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| anyhow!("{} has no [tool.harbor] table", path))
+    }
+}
+
+/// Set `path` in `table` to `value`, creating tables along the way
+///
+/// `inline` is whether `table` is an inline table, whose new child tables
+/// must be inline too. `key` is the whole key path, for errors.
+fn set_in(table: &mut dyn TableLike, path: &[Segment], value: Value, inline: bool, key: &str) -> anyhow::Result<()> {
+    let Some(Segment::Key(name)) = path.first() else {
+        bail!("{}: nested arrays cannot be edited", key);
+    };
+
+    match path.get(1) {
+        None => {
+            match table.get_mut(name) {
+                // Keep the comments around the old value
+                Some(Item::Value(old)) => {
+                    let decor = old.decor().clone();
+                    *old = value;
+                    *old.decor_mut() = decor;
+                }
+                _ => {
+                    table.insert(name, Item::Value(value));
+                }
+            }
+            Ok(())
+        }
+        Some(Segment::Key(_)) => {
+            if !table.contains_key(name) {
+                let child = if inline {
+                    Item::Value(Value::InlineTable(InlineTable::new()))
+                } else {
+                    let mut child = Table::new();
+                    child.set_implicit(true);
+                    Item::Table(child)
+                };
+                table.insert(name, child);
+            }
+            let child = table.get_mut(name).unwrap_or_else(|| unreachable!("inserted above"));
+            let inline = child.is_inline_table();
+            let child = child
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow!("{}: {} is not a table", key, name))?;
+            set_in(child, &path[1..], value, inline, key)
+        }
+        Some(Segment::Index(i)) => {
+            let missing = || anyhow!("{}: {}[{}] does not exist", key, name, i);
+            match table.get_mut(name) {
+                Some(Item::ArrayOfTables(tables)) => {
+                    let element = tables.get_mut(*i).ok_or_else(missing)?;
+                    if path.len() == 2 {
+                        bail!("{}: a [[{}]] table cannot be replaced by a value", key, name);
+                    }
+                    set_in(element, &path[2..], value, false, key)
+                }
+                Some(Item::Value(Value::Array(items))) => {
+                    let element = items.get_mut(*i).ok_or_else(missing)?;
+                    if path.len() == 2 {
+                        let decor = element.decor().clone();
+                        *element = value;
+                        *element.decor_mut() = decor;
+                        return Ok(());
+                    }
+                    let Value::InlineTable(element) = element else {
+                        bail!("{}: {}[{}] is not a table", key, name, i);
+                    };
+                    set_in(element, &path[2..], value, true, key)
+                }
+                _ => Err(missing()),
+            }
+        }
+    }
+}
+
+/// Remove `path` from `table`; returns whether it was there
+fn unset_in(table: &mut dyn TableLike, path: &[Segment]) -> bool {
+    let Some(Segment::Key(name)) = path.first() else {
+        return false;
+    };
+
+    match path.get(1) {
+        None => table.remove(name).is_some(),
+        Some(Segment::Key(_)) => table
+            .get_mut(name)
+            .and_then(Item::as_table_like_mut)
+            .is_some_and(|child| unset_in(child, &path[1..])),
+        Some(Segment::Index(i)) => match table.get_mut(name) {
+            Some(Item::ArrayOfTables(tables)) if path.len() == 2 && *i < tables.len() => {
+                tables.remove(*i);
+                true
+            }
+            Some(Item::ArrayOfTables(tables)) => tables
+                .get_mut(*i)
+                .is_some_and(|element| unset_in(element, &path[2..])),
+            Some(Item::Value(Value::Array(items))) if path.len() == 2 && *i < items.len() => {
+                items.remove(*i);
+                true
+            }
+            Some(Item::Value(Value::Array(items))) => match items.get_mut(*i) {
+                Some(Value::InlineTable(element)) => unset_in(element, &path[2..]),
+                _ => false,
+            },
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_unset() {
+        let input = r#"# Reports
+[app]
+name = "Reports"  # shown in the title bar
+
+[backend]
+command = "gunicorn"
+# Slow on first start
+startup_timeout = 60
+
+[frontend]
+url = "http::unix//${socket}/"
+width = 1200  # fits a laptop
+"#;
+        let mut editor = ConfigEditor::parse(Path::new("app.toml"), input).unwrap();
+        editor.set("frontend.width", "1400").unwrap();
+        editor.set("app.version", "2.0").unwrap();
+        editor.set("backend.env.API_URL", "http://localhost/api").unwrap();
+        editor.unset("backend.startup_timeout").unwrap();
+        editor.check().unwrap();
+        assert_eq!(
+            editor.contents(),
+            r#"# Reports
+[app]
+name = "Reports"  # shown in the title bar
+version = "2.0"
+
+[backend]
+command = "gunicorn"
+
+[backend.env]
+API_URL = "http://localhost/api"
+
+[frontend]
+url = "http::unix//${socket}/"
+width = 1400  # fits a laptop
+"#
+        );
+
+        let err = |key: &str, value: &str| {
+            let mut editor = ConfigEditor::parse(Path::new("app.toml"), input).unwrap();
+            editor.set(key, value).unwrap_err().to_string()
+        };
+        assert_eq!(err("frontend.width", "wide"), "frontend.width: expected an integer, got \"wide\"");
+        assert_eq!(err("frontend.widht", "1400"), "frontend.widht: unknown key, did you mean 'width'?");
+        assert_eq!(
+            err("backend.readiness.type", "tcp"),
+            "backend.readiness.type: \"tcp\" is not one of \"http\", \"log-line\", \"file\", \"notify\""
+        );
+
+        // Each value fits the schema, but the readiness check is incomplete
+        editor.set("backend.readiness.type", "log-line").unwrap();
+        let err = editor.check().unwrap_err().to_string();
+        assert!(err.contains("missing field `pattern`"), "{}", err);
+        assert!(editor.save().is_err());
+
+        let err = editor.unset("backend.command").unwrap_err();
+        assert_eq!(err.to_string(), "backend.command: required, so it cannot be unset");
+        assert!(editor.unset("frontend.height").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Backend health monitoring
+//!
+//! Once an app's backends are started, one supervisor thread periodically
+//! checks that each process is still alive and, if its `health.path` is
+//! set, that an HTTP liveness request over its socket succeeds. Failed
+//! backends are restarted as their restart policy allows, each on a thread
+//! of its own so that a slow start does not hold up checking the others;
+//! once the policy gives up (or says not to restart) the backend is
+//! reported as permanently failed, or as stopped if it exited cleanly.
+
+use crate::backend::{BackendError, BackendManager};
+use crate::config::{HealthConfig, HttpReadinessConfig};
+use crate::readiness::HttpProbe;
+use crate::restart::{Failure, RestartDecision};
+use crate::socket::BackendSocket;
+use log::{debug, info, warn};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the supervisor checks whether a restart has finished
+const RESTART_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Lifecycle state of a supervised backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendState {
+    /// Being started for the first time
+    Starting,
+    /// Process alive and liveness checks passing
+    Running,
+    /// Process alive but liveness checks failing
+    Unhealthy(String),
+    /// Being restarted after a failure
+    Restarting,
+    /// Stopped on request
+    Stopped,
+    /// Down and will not be restarted
+    Failed(String),
+}
+
+impl BackendState {
+    /// Whether the backend is down for good
+    pub fn is_failed(&self) -> bool {
+        matches!(self, BackendState::Failed(_))
+    }
+}
+
+impl fmt::Display for BackendState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendState::Starting => write!(f, "starting"),
+            BackendState::Running => write!(f, "running"),
+            BackendState::Unhealthy(reason) => write!(f, "unhealthy ({})", reason),
+            BackendState::Restarting => write!(f, "restarting"),
+            BackendState::Stopped => write!(f, "stopped"),
+            BackendState::Failed(reason) => write!(f, "failed ({})", reason),
+        }
+    }
+}
+
+/// Callback invoked on every backend state transition, with the backend name
+pub type StateCallback = Arc<dyn Fn(&str, &BackendState) + Send + Sync>;
+
+/// Shared, observable state of one backend
+#[derive(Clone)]
+pub struct StateCell {
+    name: String,
+    state: Arc<Mutex<BackendState>>,
+    callback: Option<StateCallback>,
+}
+
+impl StateCell {
+    /// Create a cell for backend `name` holding `initial`
+    pub fn new(
+        name: impl Into<String>,
+        initial: BackendState,
+        callback: Option<StateCallback>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            state: Arc::new(Mutex::new(initial)),
+            callback,
+        }
+    }
+
+    /// Get the backend name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the current state
+    pub fn get(&self) -> BackendState {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Change the state, logging and reporting it if it differs
+    pub fn set(&self, next: BackendState) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if *state == next {
+            return;
+        }
+
+        match next {
+            BackendState::Unhealthy(_) | BackendState::Failed(_) => {
+                warn!("Backend '{}': {} -> {}", self.name, state, next)
+            }
+            _ => info!("Backend '{}': {} -> {}", self.name, state, next),
+        }
+        *state = next.clone();
+        drop(state);
+
+        if let Some(ref callback) = self.callback {
+            callback(&self.name, &next);
+        }
+    }
+}
+
+/// Combine per-backend states into the state of the app as a whole
+///
+/// The app has failed if any backend has, and is only running once every
+/// backend is.
+pub fn combined_state(states: &[(String, BackendState)]) -> BackendState {
+    if let Some((name, BackendState::Failed(reason))) =
+        states.iter().find(|(_, s)| s.is_failed())
+    {
+        return BackendState::Failed(format!("{}: {}", name, reason));
+    }
+
+    let rank = |state: &BackendState| match state {
+        BackendState::Stopped => 0,
+        BackendState::Starting => 1,
+        BackendState::Restarting => 2,
+        BackendState::Unhealthy(_) => 3,
+        BackendState::Running => 4,
+        BackendState::Failed(_) => 5,
+    };
+    states
+        .iter()
+        .map(|(_, s)| s)
+        .min_by_key(|s| rank(s))
+        .cloned()
+        .unwrap_or(BackendState::Stopped)
+}
+
+/// A running backend handed to the health monitor
+#[derive(Clone)]
+pub struct SupervisedBackend {
+    /// The backend's manager, shared with the app
+    pub manager: Arc<Mutex<BackendManager>>,
+    /// Where the backend's state is reported
+    pub state: StateCell,
+}
+
+/// Supervisor thread that watches all of an app's running backends
+pub struct HealthMonitor {
+    handle: Option<JoinHandle<()>>,
+    stop: Option<Sender<()>>,
+    backends: Vec<SupervisedBackend>,
+}
+
+impl HealthMonitor {
+    /// Start monitoring `backends`, which must already be running
+    pub fn spawn(backends: Vec<SupervisedBackend>) -> std::io::Result<Self> {
+        let (stop, stop_rx) = mpsc::channel();
+        let supervisor = Supervisor::new(&backends, stop_rx);
+
+        let handle = std::thread::Builder::new()
+            .name("harbor-health".to_string())
+            .spawn(move || supervisor.run())?;
+
+        Ok(Self {
+            handle: Some(handle),
+            stop: Some(stop),
+            backends,
+        })
+    }
+
+    /// Get the current state of each backend
+    pub fn states(&self) -> Vec<(String, BackendState)> {
+        self.backends
+            .iter()
+            .map(|b| (b.state.name().to_string(), b.state.get()))
+            .collect()
+    }
+
+    /// Stop the supervisor thread and wait for it to exit
+    pub fn stop(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Supervisor thread state
+struct Supervisor {
+    watches: Vec<Watch>,
+    stop: Receiver<()>,
+}
+
+impl Supervisor {
+    fn new(backends: &[SupervisedBackend], stop: Receiver<()>) -> Self {
+        Self {
+            watches: backends.iter().cloned().map(Watch::new).collect(),
+            stop,
+        }
+    }
+
+    /// Check each backend when it is due until all have failed for good or
+    /// we are stopped
+    fn run(mut self) {
+        loop {
+            let now = Instant::now();
+            let Some(wake) = self
+                .watches
+                .iter()
+                .filter(|w| !w.done)
+                .map(|w| w.next_due())
+                .min()
+            else {
+                break;
+            };
+
+            // Any message or a dropped sender means stop
+            let timeout = wake.saturating_duration_since(now);
+            if !matches!(self.stop.recv_timeout(timeout), Err(RecvTimeoutError::Timeout)) {
+                break;
+            }
+
+            let now = Instant::now();
+            for watch in self.watches.iter_mut().filter(|w| !w.done) {
+                if watch.next_due() <= now {
+                    watch.tick();
+                }
+            }
+        }
+
+        // Let restarts under way finish, so that none outlives the monitor
+        for watch in &mut self.watches {
+            if let Some(restart) = watch.restart.take() {
+                let _ = restart.join();
+            }
+        }
+    }
+}
+
+/// Health checking and restart state for one backend
+struct Watch {
+    backend: SupervisedBackend,
+    interval: Duration,
+    liveness: Option<HttpProbe>,
+    timeout: Duration,
+    threshold: u32,
+    socket: BackendSocket,
+    failures: u32,
+    next_check: Instant,
+    pending_restart: Option<(Instant, Failure)>,
+    /// Restart under way on its own thread
+    restart: Option<JoinHandle<Result<(), BackendError>>>,
+    done: bool,
+}
+
+impl Watch {
+    fn new(backend: SupervisedBackend) -> Self {
+        let (health, socket) = {
+            let manager = backend.manager.lock().unwrap_or_else(|e| e.into_inner());
+            (
+                manager.config().health.clone(),
+                manager.socket().clone(),
+            )
+        };
+        let interval = Duration::from_millis(health.interval_ms.max(10));
+
+        Self {
+            backend,
+            interval,
+            liveness: liveness_probe(&health),
+            timeout: Duration::from_millis(health.timeout_ms),
+            threshold: health.failure_threshold.max(1),
+            socket,
+            failures: 0,
+            next_check: Instant::now() + interval,
+            pending_restart: None,
+            restart: None,
+            done: false,
+        }
+    }
+
+    /// When this backend next needs attention
+    fn next_due(&self) -> Instant {
+        match self.pending_restart {
+            Some((at, _)) => at,
+            None => self.next_check,
+        }
+    }
+
+    /// Start a due restart, see whether one has finished, or run one round
+    /// of checks
+    fn tick(&mut self) {
+        if let Some((_, failure)) = self.pending_restart.take() {
+            self.restart(failure);
+            return;
+        }
+        if let Some(restart) = self.restart.take() {
+            if restart.is_finished() {
+                self.restarted(restart.join());
+            } else {
+                self.restart = Some(restart);
+                self.next_check = Instant::now() + RESTART_POLL_INTERVAL;
+            }
+            return;
+        }
+
+        self.next_check = Instant::now() + self.interval;
+        let exit_status = self.manager().exit_status();
+        let failure = match exit_status {
+            Some(status) => Some(Failure::Exited(status)),
+            None => self.check_liveness(),
+        };
+
+        if let Some(failure) = failure {
+            self.handle_failure(failure);
+        }
+    }
+
+    /// Ask the restart policy what to do about a failure
+    fn handle_failure(&mut self, failure: Failure) {
+        let decision = self.manager().record_failure(&failure);
+        match decision {
+            RestartDecision::Restart(delay) => {
+                warn!(
+                    "Backend '{}' failed ({}); restarting in {:?}",
+                    self.backend.state.name(),
+                    failure,
+                    delay
+                );
+                self.backend.state.set(BackendState::Restarting);
+                self.pending_restart = Some((Instant::now() + delay, failure));
+            }
+            RestartDecision::Stop => {
+                self.backend.state.set(final_state(&failure));
+                self.done = true;
+            }
+            RestartDecision::GiveUp(e) => {
+                self.backend.state.set(BackendState::Failed(e.to_string()));
+                self.done = true;
+            }
+        }
+    }
+
+    /// Restart the backend after its backoff delay
+    ///
+    /// Starting can take up to `startup_timeout`, so it runs on a thread of
+    /// its own; `tick` picks up the result.
+    fn restart(&mut self, failure: Failure) {
+        let name = self.backend.state.name().to_string();
+        debug!("Restarting backend '{}' after: {}", name, failure);
+        let manager = Arc::clone(&self.backend.manager);
+        let spawned = std::thread::Builder::new()
+            .name(format!("harbor-restart-{}", name))
+            .spawn(move || manager.lock().unwrap_or_else(|e| e.into_inner()).restart());
+        match spawned {
+            Ok(handle) => {
+                self.restart = Some(handle);
+                self.next_check = Instant::now() + RESTART_POLL_INTERVAL;
+            }
+            Err(e) => self.handle_failure(Failure::StartFailed(e.to_string())),
+        }
+    }
+
+    /// Handle the result of a restart thread
+    fn restarted(&mut self, result: std::thread::Result<Result<(), BackendError>>) {
+        match result {
+            Ok(Ok(())) => {
+                self.failures = 0;
+                self.next_check = Instant::now() + self.interval;
+                self.backend.state.set(BackendState::Running);
+            }
+            Ok(Err(e)) => self.handle_failure(Failure::StartFailed(e.to_string())),
+            Err(_) => self.handle_failure(Failure::StartFailed("restart thread panicked".to_string())),
+        }
+    }
+
+    /// Probe the liveness endpoint; returns a failure once the threshold of
+    /// consecutive failed probes is reached
+    fn check_liveness(&mut self) -> Option<Failure> {
+        let Some(ref probe) = self.liveness else {
+            self.backend.state.set(BackendState::Running);
+            return None;
+        };
+
+        let result = probe.probe(&self.socket, self.timeout);
+        if result.is_ready() {
+            self.failures = 0;
+            self.backend.state.set(BackendState::Running);
+            return None;
+        }
+
+        self.failures += 1;
+        if self.failures >= self.threshold {
+            Some(Failure::Unhealthy(format!(
+                "liveness check failed {} times: {}",
+                self.failures, result
+            )))
+        } else {
+            self.backend
+                .state
+                .set(BackendState::Unhealthy(result.to_string()));
+            None
+        }
+    }
+
+    fn manager(&self) -> std::sync::MutexGuard<'_, BackendManager> {
+        self.backend
+            .manager
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// State of a backend that will not be restarted after `failure`
+///
+/// A clean exit (under the `on-failure` or `never` policy) is a stop, not
+/// a failure.
+fn final_state(failure: &Failure) -> BackendState {
+    if failure.is_clean_exit() {
+        BackendState::Stopped
+    } else {
+        BackendState::Failed(failure.to_string())
+    }
+}
+
+/// Build the HTTP liveness probe, if a path is configured
+fn liveness_probe(health: &HealthConfig) -> Option<HttpProbe> {
+    let path = health.path.clone()?;
+    Some(HttpProbe::new(HttpReadinessConfig {
+        method: health.method.clone(),
+        path,
+        status: health.status,
+        body_contains: None,
+        interval_ms: health.interval_ms,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_state() {
+        let states = |list: &[BackendState]| -> Vec<(String, BackendState)> {
+            list.iter()
+                .enumerate()
+                .map(|(i, s)| (format!("b{}", i), s.clone()))
+                .collect()
+        };
+
+        assert_eq!(combined_state(&[]), BackendState::Stopped);
+        assert_eq!(
+            combined_state(&states(&[BackendState::Running, BackendState::Running])),
+            BackendState::Running
+        );
+        assert_eq!(
+            combined_state(&states(&[BackendState::Running, BackendState::Restarting])),
+            BackendState::Restarting
+        );
+        assert_eq!(
+            combined_state(&states(&[
+                BackendState::Restarting,
+                BackendState::Failed("crash loop".to_string())
+            ])),
+            BackendState::Failed("b1: crash loop".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_final_state() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        assert_eq!(final_state(&Failure::Exited(ExitStatus::from_raw(0))), BackendState::Stopped);
+        assert!(final_state(&Failure::Exited(ExitStatus::from_raw(256))).is_failed());
+        assert!(final_state(&Failure::Unhealthy("timeout".to_string())).is_failed());
+    }
+}
@@ -0,0 +1,201 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Minimal HTTP/1.1 client over the backend socket
+//!
+//! Harbor only needs to issue small requests to its own backend (readiness
+//! and health probes), so this sends a single request with
+//! `Connection: close` and reads the response until EOF or until
+//! `Content-Length` bytes of body have arrived.
+
+use crate::socket::BackendSocket;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+/// Upper bound on response bytes read, to keep probes cheap
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// A parsed HTTP response
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    /// Status code
+    pub status: u16,
+    /// Response headers, in order received
+    pub headers: Vec<(String, String)>,
+    /// Response body (lossily decoded, possibly truncated)
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Get the first header with the given name (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Send a request over a Unix socket and read the response
+#[cfg(unix)]
+pub fn request(
+    socket: &BackendSocket,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> std::io::Result<HttpResponse> {
+    let mut stream = socket.connect()?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    exchange(&mut stream, method, path, body)
+}
+
+/// Send a request over a Unix socket and read the response
+#[cfg(not(unix))]
+pub fn request(
+    _socket: &BackendSocket,
+    _method: &str,
+    _path: &str,
+    _body: Option<&str>,
+    _timeout: Duration,
+) -> std::io::Result<HttpResponse> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "HTTP over named pipes is not supported yet",
+    ))
+}
+
+/// Write a request to `stream` and parse the response
+pub fn exchange<S: Read + Write>(
+    stream: &mut S,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> std::io::Result<HttpResponse> {
+    let body = body.unwrap_or("");
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nUser-Agent: harbor\r\nAccept: */*\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    let mut raw = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = match stream.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            // A timeout after the full head arrived still gives a usable status
+            Err(e) if !raw.is_empty() && is_timeout(&e) => break,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            break;
+        }
+        raw.extend_from_slice(&chunk[..n]);
+        if raw.len() >= MAX_RESPONSE_BYTES || is_complete(&raw) {
+            break;
+        }
+    }
+
+    parse_response(&raw)
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Whether `raw` holds the head and a `Content-Length` body
+fn is_complete(raw: &[u8]) -> bool {
+    let Some(head_end) = find_head_end(raw) else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&raw[..head_end]);
+    let content_length = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse::<usize>().ok()
+        } else {
+            None
+        }
+    });
+    match content_length {
+        Some(len) => raw.len() >= head_end + 4 + len,
+        None => false,
+    }
+}
+
+fn find_head_end(raw: &[u8]) -> Option<usize> {
+    raw.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// Parse a raw HTTP/1.x response
+pub fn parse_response(raw: &[u8]) -> std::io::Result<HttpResponse> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+
+    if raw.is_empty() {
+        return Err(invalid("empty response"));
+    }
+
+    let head_end = find_head_end(raw).unwrap_or(raw.len());
+    let head = String::from_utf8_lossy(&raw[..head_end]);
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    if !version.starts_with("HTTP/") {
+        return Err(invalid("malformed status line"));
+    }
+    let status = parts
+        .next()
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| invalid("malformed status code"))?;
+
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+
+    let body_start = (head_end + 4).min(raw.len());
+    let body = String::from_utf8_lossy(&raw[body_start..]).into_owned();
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let raw = b"HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain\r\nContent-Length: 8\r\n\r\nstarting";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.status, 503);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body, "starting");
+        assert!(is_complete(raw));
+        assert!(!is_complete(&raw[..raw.len() - 1]));
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(parse_response(b"").is_err());
+        assert!(parse_response(b"SSH-2.0-OpenSSH\r\n").is_err());
+    }
+}
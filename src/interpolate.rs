@@ -0,0 +1,193 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Variable interpolation in app.toml
+//!
+//! Any string value in the configuration may refer to:
+//!
+//! - `${env:VAR}`: an environment variable, which must be set
+//! - `${env:VAR:-default}`: an environment variable, or `default` if it is
+//!   unset or empty
+//! - `${app_dir}`: the directory containing app.toml
+//! - `${app.name}`: the app name
+//! - `${data_dir}`: the app's data directory
+//! - `${socket}`: the backend's socket
+//!
+//! `$${` stands for a literal `${`. Errors name the key whose value could
+//! not be interpolated.
+
+use anyhow::{anyhow, bail};
+use std::path::PathBuf;
+
+/// Values available to `${...}` references
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    /// Value of `${app.name}`
+    pub app_name: Option<String>,
+    /// Value of `${app_dir}`
+    pub app_dir: Option<PathBuf>,
+    /// Value of `${data_dir}`
+    pub data_dir: Option<PathBuf>,
+    /// Value of `${socket}`
+    pub socket: Option<String>,
+}
+
+impl Variables {
+    /// Look up a variable other than `env:`
+    fn get(&self, name: &str) -> Option<Option<String>> {
+        let path = |p: &Option<PathBuf>| p.as_ref().map(|p| p.to_string_lossy().into_owned());
+        match name {
+            "app.name" => Some(self.app_name.clone()),
+            "app_dir" => Some(path(&self.app_dir)),
+            "data_dir" => Some(path(&self.data_dir)),
+            "socket" => Some(self.socket.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Expand the `${...}` references in `input`, the value of `key`
+pub fn interpolate(input: &str, key: &str, vars: &Variables) -> anyhow::Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${") {
+        // `$${` is an escaped `${`
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        output.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| anyhow!("{}: unterminated '${{' in {:?}", key, input))?;
+        output.push_str(&resolve(&reference[..end], key, vars)?);
+        rest = &reference[end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Expand references in every string within `value`
+///
+/// `key` is the dotted path of `value`, used in error messages.
+pub fn interpolate_value(value: &mut toml::Value, key: &str, vars: &Variables) -> anyhow::Result<()> {
+    match value {
+        toml::Value::String(s) => *s = interpolate(s, key, vars)?,
+        toml::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", key, i), vars)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, item) in table.iter_mut() {
+                interpolate_value(item, &join_key(key, name), vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Append `name` to the dotted key path `key`
+pub fn join_key(key: &str, name: &str) -> String {
+    if key.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", key, name)
+    }
+}
+
+/// Resolve the contents of one `${...}`
+fn resolve(reference: &str, key: &str, vars: &Variables) -> anyhow::Result<String> {
+    if let Some(env) = reference.strip_prefix("env:") {
+        let (name, default) = match env.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (env, None),
+        };
+        if name.is_empty() {
+            bail!("{}: '${{env:}}' needs a variable name", key);
+        }
+
+        return match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+            (Ok(value), _) => Ok(value),
+            (Err(_), Some(default)) => Ok(default.to_string()),
+            (Err(_), None) => Err(anyhow!(
+                "{}: environment variable {} is not set (use ${{env:{}:-default}} to give a default)",
+                key,
+                name,
+                name
+            )),
+        };
+    }
+
+    match vars.get(reference) {
+        Some(Some(value)) => Ok(value),
+        Some(None) => Err(anyhow!("{}: ${{{}}} is not available here", key, reference)),
+        None => Err(anyhow!(
+            "{}: unknown variable ${{{}}} (expected env:NAME, app_dir, app.name, data_dir or socket)",
+            key,
+            reference
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Variables {
+        Variables {
+            app_name: Some("My App".to_string()),
+            app_dir: Some(PathBuf::from("/home/me/my-app")),
+            data_dir: None,
+            socket: Some("/run/user/1000/harbor/my-app/web.sock".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        std::env::set_var("HARBOR_TEST_INTERPOLATE", "value");
+        std::env::set_var("HARBOR_TEST_INTERPOLATE_EMPTY", "");
+        let expand = |s: &str| interpolate(s, "backend.args[0]", &vars());
+
+        assert_eq!(expand("plain").unwrap(), "plain");
+        assert_eq!(expand("${env:HARBOR_TEST_INTERPOLATE}").unwrap(), "value");
+        assert_eq!(expand("${env:HARBOR_TEST_INTERPOLATE_UNSET:-a:b}").unwrap(), "a:b");
+        assert_eq!(expand("${env:HARBOR_TEST_INTERPOLATE_EMPTY:-fallback}").unwrap(), "fallback");
+        assert_eq!(
+            expand("${app_dir}/app.py for ${app.name}").unwrap(),
+            "/home/me/my-app/app.py for My App"
+        );
+        assert_eq!(
+            expand("unix:${socket}").unwrap(),
+            "unix:/run/user/1000/harbor/my-app/web.sock"
+        );
+        assert_eq!(expand("$${app_dir} costs $5").unwrap(), "${app_dir} costs $5");
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        let error = |s: &str| interpolate(s, "backend.env.TOKEN", &vars()).unwrap_err().to_string();
+
+        let unset = error("${env:HARBOR_TEST_INTERPOLATE_UNSET}");
+        assert!(unset.starts_with("backend.env.TOKEN: environment variable HARBOR_TEST_INTERPOLATE_UNSET"));
+        assert!(error("${data_dir}").contains("${data_dir} is not available"));
+        assert!(error("${home}").contains("unknown variable ${home}"));
+        assert!(error("${app_dir").contains("unterminated"));
+    }
+
+    #[test]
+    fn test_interpolate_value_keys() {
+        let mut value = toml::Value::Table(toml::from_str("args = ['ok', '${nope}']").unwrap());
+        let err = interpolate_value(&mut value, "backend", &vars()).unwrap_err();
+        assert!(err.to_string().starts_with("backend.args[1]: unknown variable"));
+    }
+}
@@ -0,0 +1,242 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Unknown-key detection
+//!
+//! serde skips keys it does not know, so a misspelt `startup_timout` or
+//! `[fronted]` silently falls back to the default. The known keys of each
+//! section are taken from the field lists serde derives for the config
+//! structs, so they cannot drift from what is actually read; any other key
+//! is reported along with the closest known one.
+
+use crate::config::{
+    AppConfig, BackendConfig, FileReadinessConfig, FrontendConfig, HarborConfig, HealthConfig,
+    HttpReadinessConfig, LogLineReadinessConfig, LoggingConfig, NotifyReadinessConfig,
+    RestartConfig, SettingsConfig,
+};
+use crate::interpolate::join_key;
+use crate::validate::Diagnostic;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+/// A section of app.toml with a fixed set of keys
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    Root,
+    /// `[profile]`, whose tables are each like the root
+    Profiles,
+    App,
+    Backend,
+    Readiness,
+    Health,
+    Restart,
+    Frontend,
+    Settings,
+    Logging,
+}
+
+impl Section {
+    /// Keys allowed in `table`, a table of this section, if they are known
+    fn keys(self, table: &toml::Table) -> Option<Vec<&'static str>> {
+        let keys = match self {
+            Section::Root => {
+                let fields = field_names::<HarborConfig>();
+                return Some(fields.iter().copied().chain(["profile"]).collect());
+            }
+            Section::Profiles => return None,
+            Section::App => field_names::<AppConfig>(),
+            Section::Backend => field_names::<BackendConfig>(),
+            Section::Health => field_names::<HealthConfig>(),
+            Section::Restart => field_names::<RestartConfig>(),
+            Section::Frontend => field_names::<FrontendConfig>(),
+            Section::Settings => field_names::<SettingsConfig>(),
+            Section::Logging => field_names::<LoggingConfig>(),
+            Section::Readiness => {
+                // The keys depend on the strategy, which defaults to http
+                let fields = match table.get("type").and_then(toml::Value::as_str) {
+                    Some("http") | None => field_names::<HttpReadinessConfig>(),
+                    Some("log-line") => field_names::<LogLineReadinessConfig>(),
+                    Some("file") => field_names::<FileReadinessConfig>(),
+                    Some("notify") => field_names::<NotifyReadinessConfig>(),
+                    // Deserialization reports the unknown type
+                    Some(_) => return None,
+                };
+                return Some(std::iter::once("type").chain(fields.iter().copied()).collect());
+            }
+        };
+        Some(keys.to_vec())
+    }
+
+    /// Section of the table or array of tables under `key`, if it is checked
+    fn child(self, key: &str) -> Option<Section> {
+        match (self, key) {
+            (Section::Root, "profile") => Some(Section::Profiles),
+            (Section::Root, "app") => Some(Section::App),
+            (Section::Root, "backend" | "backends") => Some(Section::Backend),
+            (Section::Root, "frontend") => Some(Section::Frontend),
+            (Section::Root, "settings") => Some(Section::Settings),
+            (Section::Root, "logging") => Some(Section::Logging),
+            (Section::Backend, "readiness") => Some(Section::Readiness),
+            (Section::Backend, "health") => Some(Section::Health),
+            (Section::Backend, "restart") => Some(Section::Restart),
+            _ => None,
+        }
+    }
+}
+
+/// Find the keys in a parsed app.toml that Harbor does not read
+///
+/// Each is reported as a warning naming the key, with a suggestion if a
+/// known key is similar.
+pub fn unknown_keys(table: &toml::Table) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check_section(table, "", Section::Root, &mut diagnostics);
+    diagnostics
+}
+
+fn check_section(table: &toml::Table, key: &str, section: Section, out: &mut Vec<Diagnostic>) {
+    if let Section::Profiles = section {
+        for (name, value) in table {
+            if let toml::Value::Table(profile) = value {
+                check_section(profile, &join_key(key, name), Section::Root, out);
+            }
+        }
+        return;
+    }
+    let Some(known) = section.keys(table) else {
+        return;
+    };
+
+    for (name, value) in table {
+        let path = join_key(key, name);
+        if !known.contains(&name.as_str()) {
+            let message = match suggestion(name, &known) {
+                Some(similar) => format!("unknown key, did you mean '{}'?", similar),
+                None => format!("unknown key (expected one of: {})", known.join(", ")),
+            };
+            out.push(Diagnostic::warning(path, message));
+            continue;
+        }
+
+        let Some(child) = section.child(name) else {
+            continue;
+        };
+        match value {
+            toml::Value::Table(table) => check_section(table, &path, child, out),
+            toml::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if let toml::Value::Table(table) = item {
+                        check_section(table, &format!("{}[{}]", path, i), child, out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The known key most similar to `name`, if any is close enough
+///
+/// Up to one edit (counting a swap of adjacent letters as one) per three
+/// letters of `name` is considered a typo.
+pub(crate) fn suggestion<'a>(name: &str, known: &[&'a str]) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    known
+        .iter()
+        .map(|k| (strsim::osa_distance(name, k), *k))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, k)| k)
+}
+
+/// Field names of a struct deriving `Deserialize`
+///
+/// The derived impl passes its field list to `deserialize_struct`; this
+/// deserializer records it and then bails out.
+pub(crate) fn field_names<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Variant names of an externally tagged enum deriving `Deserialize`,
+/// collected the same way from `deserialize_enum`
+pub(crate) fn variant_names<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    field_names::<T>()
+}
+
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("field names collected"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("variant names collected"))
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_keys() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            [app]
+            name = "Typos"
+
+            [backend]
+            command = "app"
+            startup_timout = 10
+            env = { ANY_NAME = "is fine" }
+            readiness = { type = "log-line", pattern = "ready", path = "/health" }
+
+            [[backends]]
+            command = "worker"
+            restart = { policy = "always", max_restart = 3 }
+
+            [fronted]
+            url = "http::unix///tmp/app.sock/"
+            "#,
+        )
+        .unwrap();
+
+        let found: Vec<String> = unknown_keys(&table).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "warning: backend.readiness.path: unknown key (expected one of: type, pattern)",
+                "warning: backend.startup_timout: unknown key, did you mean 'startup_timeout'?",
+                "warning: backends[0].restart.max_restart: unknown key, did you mean 'max_restarts'?",
+                "warning: fronted: unknown key, did you mean 'frontend'?",
+            ]
+        );
+    }
+}
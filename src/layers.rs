@@ -0,0 +1,746 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Configuration layers
+//!
+//! The effective configuration is built up from layers, lowest precedence
+//! first:
+//!
+//! 1. The app's config file, over the files it `extends`, with the
+//!    selected `[profile.<name>]`
+//! 2. The user's overrides in `~/.config/harbor/<app>.toml`
+//! 3. `HARBOR_*` environment variables, e.g. `HARBOR_FRONTEND__WIDTH=1200`
+//! 4. `--set key.path=value` flags
+//!
+//! Layers are merged as TOML tables before anything is deserialized, and
+//! the layer each value came from is recorded in a `Provenance`.
+
+use crate::interpolate::join_key;
+use anyhow::{anyhow, bail, Context};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Prefix of environment variables that override config values
+pub const ENV_PREFIX: &str = "HARBOR_";
+
+/// Separator between key path segments in environment variable names
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Root key naming the config file a config file extends
+pub const EXTENDS_KEY: &str = "extends";
+
+/// Line numbers of the keys in a TOML file, by key path
+pub type KeyLines = BTreeMap<String, usize>;
+
+/// Where a configuration value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Not set anywhere: the built-in default
+    Default,
+    /// The app's config file
+    File { path: PathBuf, line: Option<usize> },
+    /// A file the app's config file `extends`, directly or through others
+    Base { path: PathBuf, line: Option<usize> },
+    /// A `[profile.<name>]` table in the app's config file
+    Profile { name: String, path: PathBuf, line: Option<usize> },
+    /// The user's override file
+    UserFile { path: PathBuf, line: Option<usize> },
+    /// A `HARBOR_*` environment variable
+    Env(String),
+    /// A `--set key.path=value` flag
+    Set(String),
+    /// Code embedding Harbor, through `HarborConfig::builder()`
+    Builder,
+}
+
+impl Source {
+    /// The app's config file, without a line
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Source::File {
+            path: path.into(),
+            line: None,
+        }
+    }
+
+    /// Whether the value was set outside the app's config file
+    pub fn is_override(&self) -> bool {
+        matches!(self, Source::UserFile { .. } | Source::Env(_) | Source::Set(_))
+    }
+
+    /// Whether the value was set anywhere but the app's config file or
+    /// its profiles: in an extended file or an override
+    pub fn is_elsewhere(&self) -> bool {
+        matches!(self, Source::Base { .. }) || self.is_override()
+    }
+
+    /// The same source at `line` of its file, if it is a file
+    fn at_line(&self, at: Option<usize>) -> Self {
+        let mut source = self.clone();
+        if let Source::File { line, .. }
+        | Source::Base { line, .. }
+        | Source::Profile { line, .. }
+        | Source::UserFile { line, .. } = &mut source
+        {
+            *line = at;
+        }
+        source
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = |f: &mut fmt::Formatter<'_>, path: &PathBuf, line: &Option<usize>| match line {
+            Some(line) => write!(f, "{}:{}", path.display(), line),
+            None => write!(f, "{}", path.display()),
+        };
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File { path, line } | Source::Base { path, line } | Source::UserFile { path, line } => {
+                location(f, path, line)
+            }
+            Source::Profile { name, path, line } => {
+                write!(f, "profile {} (", name)?;
+                location(f, path, line)?;
+                write!(f, ")")
+            }
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Set(assignment) => write!(f, "--set {}", assignment),
+            Source::Builder => write!(f, "builder"),
+        }
+    }
+}
+
+/// A layer being applied: its source and, for a file, where its keys are
+#[derive(Debug, Clone)]
+pub struct Layer {
+    source: Source,
+    lines: KeyLines,
+    /// Key path of the layer's root within its file
+    root: String,
+}
+
+impl Layer {
+    /// A layer without line information
+    pub fn new(source: Source) -> Self {
+        Self {
+            source,
+            lines: KeyLines::new(),
+            root: String::new(),
+        }
+    }
+
+    /// A layer read from the TOML file `contents`
+    pub fn from_file(source: Source, contents: &str) -> Self {
+        Self {
+            lines: key_lines(contents),
+            ..Self::new(source)
+        }
+    }
+
+    /// The same layer for values found at `root` in its file, such as
+    /// `tool.harbor` in pyproject.toml
+    pub fn within(self, root: &str) -> Self {
+        Self {
+            root: root.to_string(),
+            ..self
+        }
+    }
+
+    /// Give the value at `key` within the layer the line of `from`, a key
+    /// path from the top of the file, where it was copied from
+    pub fn with_line_from(mut self, key: &str, from: &str) -> Self {
+        if let Some(&line) = self.lines.get(from) {
+            self.lines.insert(join_key(&self.root, key), line);
+        }
+        self
+    }
+
+    /// Get the layer's source
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    /// The `[profile.<name>]` layer within this file layer
+    fn profile(&self, name: &str) -> Self {
+        let path = match &self.source {
+            Source::File { path, .. } | Source::Base { path, .. } => path.clone(),
+            _ => PathBuf::new(),
+        };
+        Self {
+            source: Source::Profile {
+                name: name.to_string(),
+                path,
+                line: None,
+            },
+            lines: self.lines.clone(),
+            root: join_key(&join_key(&self.root, "profile"), name),
+        }
+    }
+
+    /// Source of the value at `origin` within the layer
+    fn source_at(&self, origin: &str) -> Source {
+        let line = self.lines.get(&join_key(&self.root, origin)).copied();
+        self.source.at_line(line)
+    }
+}
+
+/// Find the line of every key in a TOML file
+///
+/// Returns an empty map if the file does not parse; errors are reported
+/// when it is deserialized.
+pub fn key_lines(contents: &str) -> KeyLines {
+    let mut lines = KeyLines::new();
+    if let Ok(document) = toml_edit::ImDocument::parse(contents) {
+        let line_of = |span: Option<std::ops::Range<usize>>| {
+            span.map(|span| contents[..span.start].matches('\n').count() + 1)
+        };
+        collect_lines(document.as_table(), "", &line_of, &mut lines);
+    }
+    lines
+}
+
+fn collect_lines(
+    table: &dyn toml_edit::TableLike,
+    key: &str,
+    line_of: &dyn Fn(Option<std::ops::Range<usize>>) -> Option<usize>,
+    lines: &mut KeyLines,
+) {
+    for (name, item) in table.iter() {
+        let path = join_key(key, name);
+        let key_span = table.key(name).and_then(toml_edit::Key::span);
+        if let Some(line) = line_of(key_span.or_else(|| item.span())) {
+            lines.insert(path.clone(), line);
+        }
+
+        match item {
+            toml_edit::Item::Table(table) => collect_lines(table, &path, line_of, lines),
+            toml_edit::Item::Value(toml_edit::Value::InlineTable(table)) => {
+                collect_lines(table, &path, line_of, lines)
+            }
+            toml_edit::Item::ArrayOfTables(tables) => {
+                for (i, table) in tables.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    if let Some(line) = line_of(table.span()) {
+                        lines.insert(path.clone(), line);
+                    }
+                    collect_lines(table, &path, line_of, lines);
+                }
+            }
+            toml_edit::Item::Value(toml_edit::Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    if let toml_edit::Value::InlineTable(table) = item {
+                        collect_lines(table, &format!("{}[{}]", path, i), line_of, lines);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The source of each value in a configuration, by key path
+///
+/// Values are recorded at their leaves (`frontend.width`,
+/// `backends[0].args`); tables only group them.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    sources: BTreeMap<String, Source>,
+}
+
+impl Provenance {
+    /// Get the source of the value at `key`, or of the nearest enclosing
+    /// value that was recorded (for an element of an array, the array)
+    pub fn get(&self, key: &str) -> Option<&Source> {
+        let mut key = key;
+        loop {
+            if let Some(source) = self.sources.get(key) {
+                return Some(source);
+            }
+            key = &key[..key.rfind(['.', '['])?];
+        }
+    }
+
+    /// Get the source of the value at `key`, `Source::Default` if unset
+    pub fn source_of(&self, key: &str) -> Source {
+        self.get(key).cloned().unwrap_or(Source::Default)
+    }
+
+    /// Get the source of the first value recorded at or below `key`
+    pub fn find_within(&self, key: &str) -> Option<&Source> {
+        self.sources
+            .range(key.to_string()..)
+            .take_while(|(k, _)| k.starts_with(key))
+            .find(|(k, _)| is_within(k, key))
+            .map(|(_, source)| source)
+    }
+
+    /// Iterate over the recorded leaves and their sources in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.sources.iter().map(|(k, s)| (k.as_str(), s))
+    }
+
+    /// Record that every value in `table`, the whole configuration, came
+    /// from `layer`
+    pub fn record_table(&mut self, table: &toml::Table, layer: &Layer) {
+        for (name, value) in table {
+            self.record(name, name, value, layer);
+        }
+    }
+
+    /// Record that `value`, now at `key` and at `origin` within `layer`,
+    /// came from `layer`
+    ///
+    /// Anything recorded below `key` before is forgotten.
+    fn record(&mut self, key: &str, origin: &str, value: &toml::Value, layer: &Layer) {
+        self.sources.retain(|k, _| !is_within(k, key));
+        self.record_leaves(key, origin, value, layer);
+    }
+
+    fn record_leaves(&mut self, key: &str, origin: &str, value: &toml::Value, layer: &Layer) {
+        match value {
+            toml::Value::Table(table) => {
+                for (name, item) in table {
+                    self.record_leaves(&join_key(key, name), &join_key(origin, name), item, layer);
+                }
+            }
+            toml::Value::Array(items) if !items.is_empty() && items.iter().all(toml::Value::is_table) => {
+                for (i, item) in items.iter().enumerate() {
+                    let index = |path: &str| format!("{}[{}]", path, i);
+                    self.record_leaves(&index(key), &index(origin), item, layer);
+                }
+            }
+            _ => {
+                self.sources.insert(key.to_string(), layer.source_at(origin));
+            }
+        }
+    }
+}
+
+/// Whether `key` is `ancestor` or lies below it
+fn is_within(key: &str, ancestor: &str) -> bool {
+    match key.strip_prefix(ancestor) {
+        Some(rest) => ancestor.is_empty() || rest.is_empty() || rest.starts_with(['.', '[']),
+        None => false,
+    }
+}
+
+/// Deep-merge `overlay`, the whole of `layer`, into `base`
+///
+/// Tables are merged key by key and other values replaced, except that
+/// arrays of tables which all have a `name` (such as `[[backends]]`) are
+/// merged item by item, matching on `name`; unmatched items are appended.
+pub fn merge_tables(base: &mut toml::Table, overlay: toml::Table, layer: &Layer, provenance: &mut Provenance) {
+    merge_table_at(base, overlay, "", "", layer, provenance)
+}
+
+/// Merge `overlay`, at `origin` within `layer`, into `base`, at `key`
+fn merge_table_at(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    key: &str,
+    origin: &str,
+    layer: &Layer,
+    provenance: &mut Provenance,
+) {
+    for (name, value) in overlay {
+        let (path, origin) = (join_key(key, &name), join_key(origin, &name));
+        match base.get_mut(&name) {
+            Some(existing) => merge_value(existing, value, &path, &origin, layer, provenance),
+            None => {
+                provenance.record(&path, &origin, &value, layer);
+                base.insert(name, value);
+            }
+        }
+    }
+}
+
+fn merge_value(
+    base: &mut toml::Value,
+    overlay: toml::Value,
+    key: &str,
+    origin: &str,
+    layer: &Layer,
+    provenance: &mut Provenance,
+) {
+    let name = |item: &toml::Value| item.get("name").and_then(toml::Value::as_str).map(str::to_string);
+
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            merge_table_at(base, overlay, key, origin, layer, provenance)
+        }
+        (toml::Value::Array(base), toml::Value::Array(overlay))
+            if base.iter().chain(&overlay).all(|item| name(item).is_some()) =>
+        {
+            for (j, item) in overlay.into_iter().enumerate() {
+                let origin = format!("{}[{}]", origin, j);
+                match base.iter().position(|existing| name(existing) == name(&item)) {
+                    Some(i) => {
+                        let key = format!("{}[{}]", key, i);
+                        merge_value(&mut base[i], item, &key, &origin, layer, provenance)
+                    }
+                    None => {
+                        provenance.record(&format!("{}[{}]", key, base.len()), &origin, &item, layer);
+                        base.push(item);
+                    }
+                }
+            }
+        }
+        (base, overlay) => {
+            provenance.record(key, origin, &overlay, layer);
+            *base = overlay;
+        }
+    }
+}
+
+/// Remove the `[profile.<name>]` tables from a config file
+pub fn take_profiles(table: &mut toml::Table) -> anyhow::Result<toml::Table> {
+    match table.remove("profile") {
+        Some(toml::Value::Table(profiles)) => Ok(profiles),
+        Some(_) => bail!("profile: expected [profile.<name>] tables"),
+        None => Ok(toml::Table::new()),
+    }
+}
+
+/// Merge the selected profile over the config files
+///
+/// `profiles` holds the `[profile.<name>]` tables of each file of an
+/// `extends` chain, most basic file first; the profile's tables from every
+/// file that defines it are merged in that order.
+pub fn apply_profile(
+    table: &mut toml::Table,
+    profiles: &[(toml::Table, Layer)],
+    profile: Option<&str>,
+    provenance: &mut Provenance,
+) -> anyhow::Result<()> {
+    let Some(name) = profile else {
+        return Ok(());
+    };
+
+    let mut found = false;
+    for (file_profiles, file) in profiles {
+        match file_profiles.get(name) {
+            Some(toml::Value::Table(overlay)) => {
+                merge_tables(table, overlay.clone(), &file.profile(name), provenance);
+                found = true;
+            }
+            Some(_) => bail!("{}: profile.{}: expected a table", file.source(), name),
+            None => {}
+        }
+    }
+    if found {
+        return Ok(());
+    }
+
+    let names: BTreeSet<&str> = profiles
+        .iter()
+        .flat_map(|(file_profiles, _)| file_profiles.keys())
+        .map(String::as_str)
+        .collect();
+    if names.is_empty() {
+        bail!("Unknown profile '{}': no [profile.<name>] tables are defined", name)
+    }
+    let names: Vec<&str> = names.into_iter().collect();
+    bail!("Unknown profile '{}' (available: {})", name, names.join(", "))
+}
+
+/// Read the chain of files a config file `extends`
+///
+/// `table` is the config read from the file at `path` as `layer`. Each
+/// file's `extends` is resolved against its own directory. Returns every
+/// file in the chain with its layer, the most basic first, with `extends`
+/// removed.
+pub fn read_chain(table: toml::Table, layer: Layer, path: &Path) -> anyhow::Result<Vec<(toml::Table, Layer)>> {
+    let mut chain = Vec::new();
+    // Files are compared by canonical path, so `..` and symlinks cannot
+    // hide a cycle
+    let mut seen = vec![std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())];
+    let mut current = (table, layer);
+
+    loop {
+        let extends = current.0.remove(EXTENDS_KEY);
+        let name = current.1.source().to_string();
+        chain.push(current);
+
+        let extends = match extends {
+            None => break,
+            Some(toml::Value::String(extends)) => extends,
+            Some(_) => bail!("{}: {}: expected a file path", name, EXTENDS_KEY),
+        };
+        let dir = seen.last().and_then(|p| p.parent()).unwrap_or(Path::new(""));
+        let base = crate::config::resolve_path(Path::new(&extends), dir);
+        let base = std::fs::canonicalize(&base)
+            .with_context(|| format!("{}: cannot read {} {}", name, EXTENDS_KEY, base.display()))?;
+        if seen.contains(&base) {
+            let cycle: Vec<String> = seen.iter().chain([&base]).map(|p| p.display().to_string()).collect();
+            bail!("{}: {} cycle: {}", name, EXTENDS_KEY, cycle.join(" -> "));
+        }
+
+        let contents = std::fs::read_to_string(&base)
+            .with_context(|| format!("{}: cannot read {} {}", name, EXTENDS_KEY, base.display()))?;
+        let table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", base.display()))?;
+        let layer = Layer::from_file(
+            Source::Base {
+                path: base.clone(),
+                line: None,
+            },
+            &contents,
+        );
+        seen.push(base);
+        current = (table, layer);
+    }
+
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Path of the user's override file for an app:
+/// `$XDG_CONFIG_HOME/harbor/<app>.toml`, or `~/.config/harbor/<app>.toml`
+pub fn user_config_path(app_name: &str) -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    let file = format!("{}.toml", crate::config::app_dir_name(app_name));
+    Some(config_home.join("harbor").join(file))
+}
+
+/// One segment of a key path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// A table key
+    Key(String),
+    /// An index into an array
+    Index(usize),
+}
+
+/// Parse a key path such as `frontend.width` or `backends[0].args`
+pub fn parse_key_path(path: &str) -> anyhow::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (name, mut indexes) = part.split_once('[').map_or((part, ""), |(n, i)| (n, i));
+        if name.is_empty() {
+            bail!("Invalid key path '{}': empty key", path);
+        }
+        segments.push(Segment::Key(name.to_string()));
+
+        while !indexes.is_empty() {
+            let (index, rest) = indexes
+                .split_once(']')
+                .ok_or_else(|| anyhow!("Invalid key path '{}': missing ']'", path))?;
+            let index = index
+                .parse()
+                .map_err(|_| anyhow!("Invalid key path '{}': bad index '{}'", path, index))?;
+            segments.push(Segment::Index(index));
+            indexes = match rest {
+                "" => "",
+                _ => rest
+                    .strip_prefix('[')
+                    .ok_or_else(|| anyhow!("Invalid key path '{}'", path))?,
+            };
+        }
+    }
+    Ok(segments)
+}
+
+/// Format key path segments the way diagnostics and provenance do
+pub fn format_key_path(segments: &[Segment]) -> String {
+    segments.iter().fold(String::new(), |key, segment| match segment {
+        Segment::Key(name) => join_key(&key, name),
+        Segment::Index(i) => format!("{}[{}]", key, i),
+    })
+}
+
+/// Key path named by a `HARBOR_*` environment variable, if it names one
+///
+/// Segments are separated by `__` and lowercased, except below an `env`
+/// table where variable names keep their case; numeric segments are array
+/// indexes. `HARBOR_BACKENDS__0__ENV__API_URL` is `backends[0].env.API_URL`.
+/// Variables without a `__`, such as `HARBOR_PROFILE`, are not config keys.
+pub fn env_key_path(var: &str) -> Option<Vec<Segment>> {
+    let rest = var.strip_prefix(ENV_PREFIX)?;
+    if !rest.contains(ENV_SEPARATOR) {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut in_env = false;
+    for part in rest.split(ENV_SEPARATOR) {
+        if part.is_empty() {
+            return None;
+        }
+        if let Ok(index) = part.parse() {
+            segments.push(Segment::Index(index));
+            continue;
+        }
+        let name = if in_env { part.to_string() } else { part.to_lowercase() };
+        in_env = !in_env && name == "env";
+        segments.push(Segment::Key(name));
+    }
+    Some(segments)
+}
+
+/// Set the value at `path` from a string given on the command line or in
+/// the environment by `layer`, creating tables as needed
+///
+/// The string is kept as is if it replaces a string; otherwise it is read
+/// as a TOML value (`1200`, `true`, `["a", "b"]`) if it is one.
+pub fn set_value(
+    table: &mut toml::Table,
+    path: &[Segment],
+    raw: &str,
+    layer: &Layer,
+    provenance: &mut Provenance,
+) -> anyhow::Result<()> {
+    let source = layer.source();
+    let key = format_key_path(path);
+    let Some((last, parents)) = path.split_last() else {
+        bail!("{}: empty key path", source);
+    };
+
+    let mut root = toml::Value::Table(std::mem::take(table));
+    let result = (|| {
+        let mut current = &mut root;
+        for (depth, segment) in parents.iter().enumerate() {
+            current = child(current, segment, true)
+                .ok_or_else(|| {
+                    let parent = format_key_path(&path[..=depth]);
+                    anyhow!("{}: {} does not exist or is not a table", source, parent)
+                })?;
+        }
+
+        let existing = child(current, last, false);
+        let value = match existing {
+            Some(toml::Value::String(_)) => toml::Value::String(raw.to_string()),
+            _ => parse_value(raw),
+        };
+        match (current, last) {
+            (toml::Value::Table(t), Segment::Key(name)) => {
+                t.insert(name.clone(), value.clone());
+            }
+            (toml::Value::Array(items), Segment::Index(i)) if *i < items.len() => items[*i] = value.clone(),
+            _ => bail!("{}: cannot set {}", source, key),
+        }
+        provenance.record(&key, "", &value, layer);
+        Ok(())
+    })();
+
+    if let toml::Value::Table(root) = root {
+        *table = root;
+    }
+    result
+}
+
+/// Get the child of `value` named by `segment`, creating an empty table
+/// for a missing key if `create` is set
+fn child<'a>(value: &'a mut toml::Value, segment: &Segment, create: bool) -> Option<&'a mut toml::Value> {
+    match (value, segment) {
+        (toml::Value::Table(table), Segment::Key(name)) => {
+            if create {
+                let empty = || toml::Value::Table(toml::Table::new());
+                Some(table.entry(name.clone()).or_insert_with(empty))
+            } else {
+                table.get_mut(name)
+            }
+        }
+        (toml::Value::Array(items), Segment::Index(i)) => items.get_mut(*i),
+        _ => None,
+    }
+}
+
+/// Read a string as a TOML value, or keep it as a string if it is not one
+pub(crate) fn parse_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/// Split a `--set key.path=value` flag
+pub fn parse_assignment(assignment: &str) -> anyhow::Result<(Vec<Segment>, &str)> {
+    let (path, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid --set '{}': expected key.path=value", assignment))?;
+    Ok((parse_key_path(path.trim())?, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_paths() {
+        assert_eq!(
+            parse_key_path("backends[1].args").unwrap(),
+            vec![
+                Segment::Key("backends".to_string()),
+                Segment::Index(1),
+                Segment::Key("args".to_string())
+            ]
+        );
+        assert!(parse_key_path("frontend..width").is_err());
+        assert!(parse_key_path("backends[x]").is_err());
+
+        let env = env_key_path("HARBOR_BACKENDS__0__ENV__API_URL").unwrap();
+        assert_eq!(format_key_path(&env), "backends[0].env.API_URL");
+        assert_eq!(env_key_path("HARBOR_PROFILE"), None);
+        assert_eq!(env_key_path("OTHER__VAR"), None);
+    }
+
+    #[test]
+    fn test_layers() {
+        let contents = r#"
+            [frontend]
+            url = "http::unix///tmp/app.sock/"
+            width = 800
+
+            [[backends]]
+            name = "web"
+            command = "gunicorn"
+
+            [profile.dev]
+            frontend = { width = 640 }
+            "#;
+        let file = Layer::from_file(Source::file("app.toml"), contents);
+        let at = |line| Source::File {
+            path: PathBuf::from("app.toml"),
+            line: Some(line),
+        };
+        let mut provenance = Provenance::default();
+        let mut table: toml::Table = toml::from_str(contents).unwrap();
+        let profiles = take_profiles(&mut table).unwrap();
+        provenance.record_table(&table, &file);
+        apply_profile(&mut table, &[(profiles, file)], Some("dev"), &mut provenance).unwrap();
+
+        let user = Layer::new(Source::UserFile {
+            path: PathBuf::from("user.toml"),
+            line: None,
+        });
+        let overlay: toml::Table = toml::from_str("frontend.height = 900\nbackends = [{ name = \"web\", args = [\"-v\"] }]").unwrap();
+        merge_tables(&mut table, overlay, &user, &mut provenance);
+
+        let env = Layer::new(Source::Env("HARBOR_FRONTEND__HEIGHT".to_string()));
+        set_value(&mut table, &env_key_path("HARBOR_FRONTEND__HEIGHT").unwrap(), "1000", &env, &mut provenance).unwrap();
+        let set = Layer::new(Source::Set("backends[0].command=123".to_string()));
+        let (path, value) = parse_assignment("backends[0].command=123").unwrap();
+        set_value(&mut table, &path, value, &set, &mut provenance).unwrap();
+
+        assert_eq!(table["frontend"]["width"].as_integer(), Some(640));
+        assert_eq!(table["frontend"]["height"].as_integer(), Some(1000));
+        assert_eq!(table["backends"][0]["command"].as_str(), Some("123"));
+        assert_eq!(provenance.source_of("frontend.url"), at(3));
+        assert_eq!(key_lines(contents).get("backends[0]"), Some(&6));
+        assert_eq!(key_lines(contents).get("profile.dev.frontend.width"), Some(&11));
+        assert_eq!(provenance.source_of("frontend.width").to_string(), "profile dev (app.toml:11)");
+        assert_eq!(provenance.source_of("frontend.height"), *env.source());
+        assert_eq!(provenance.source_of("backends[0].args[0]"), *user.source());
+        assert_eq!(provenance.source_of("backends[0].command"), *set.source());
+        assert_eq!(provenance.source_of("frontend.title"), Source::Default);
+
+        let missing = parse_assignment("backends[3].command=x").unwrap().0;
+        assert!(set_value(&mut table, &missing, "x", &set, &mut provenance).is_err());
+    }
+}
@@ -43,6 +43,23 @@
 pub mod config;
 pub mod backend;
 pub mod app;
+pub mod logs;
+pub mod http;
+pub mod readiness;
+pub mod health;
+pub mod restart;
+pub mod activation;
+pub mod socket;
+pub mod interpolate;
+pub mod validate;
+pub mod keys;
+pub mod layers;
+pub mod show;
+pub mod manifest;
+pub mod migrate;
+pub mod schema;
+pub mod edit;
+pub mod builder;
 
 pub use config::HarborConfig;
 pub use app::HarborApp;
@@ -0,0 +1,528 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Backend output capture
+//!
+//! Backend processes are spawned with piped stdout/stderr. Each stream is
+//! drained line by line on its own thread so the child never blocks on a
+//! full pipe. Every line is tagged with the app name, backend name and
+//! stream, forwarded to the `log` crate, and kept in a bounded in-memory
+//! buffer that the rest of Harbor can query.
+//!
+//! When file logging is enabled, backend output and Harbor's own log records
+//! are also written to size/age rotated files in the app's log directory.
+
+use log::LevelFilter;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// Log target used when forwarding backend output
+pub const OUTPUT_TARGET: &str = "harbor::backend::output";
+
+/// Which output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for LogStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogStream::Stdout => write!(f, "stdout"),
+            LogStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A single captured line of backend output
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// When the line was read
+    pub timestamp: SystemTime,
+    /// Application name
+    pub app: String,
+    /// Backend name
+    pub backend: String,
+    /// Stream the line was read from
+    pub stream: LogStream,
+    /// Line contents, without the trailing newline
+    pub line: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}/{}:{}] {}", self.app, self.backend, self.stream, self.line)
+    }
+}
+
+/// Bounded ring buffer of recent backend output
+///
+/// Cloning a `LogBuffer` yields another handle to the same buffer.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<VecDeque<LogLine>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    /// Create a buffer that keeps at most `capacity` lines
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(VecDeque::with_capacity(capacity.min(1024)))),
+            capacity,
+        }
+    }
+
+    /// Append a line, evicting the oldest one if the buffer is full
+    pub fn push(&self, line: LogLine) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Get up to `n` of the most recent lines, oldest first
+    pub fn recent(&self, n: usize) -> Vec<LogLine> {
+        let lines = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let skip = lines.len().saturating_sub(n);
+        lines.iter().skip(skip).cloned().collect()
+    }
+
+    /// Get every buffered line, oldest first
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.recent(usize::MAX)
+    }
+
+    /// Number of buffered lines
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Maximum number of lines kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Discard all buffered lines
+    pub fn clear(&self) {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// When a log file is rotated and how many old files are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Rotate once the file would grow past this many bytes (0 disables)
+    pub max_size: u64,
+    /// Rotate once the file is older than this
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep (`name.1` is the newest)
+    pub keep: usize,
+}
+
+/// Append-only log file that rotates by size or age
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    written: u64,
+    opened_at: SystemTime,
+    /// Whether the last byte written ended a line; files are only rotated
+    /// between lines
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    /// Open (or create) a log file, creating its parent directory if needed
+    pub fn open<P: AsRef<Path>>(path: P, policy: RotationPolicy) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let opened_at = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            path,
+            policy,
+            file,
+            written: metadata.len(),
+            opened_at,
+            at_line_start: true,
+        })
+    }
+
+    /// Get the path of the active log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the path of the `n`th rotated file
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    /// Whether writing `len` more bytes should first rotate the file
+    fn should_rotate(&self, len: usize) -> bool {
+        if self.written == 0 || !self.at_line_start {
+            return false;
+        }
+        if self.policy.max_size > 0 && self.written + len as u64 > self.policy.max_size {
+            return true;
+        }
+        match self.policy.max_age {
+            Some(max_age) => self
+                .opened_at
+                .elapsed()
+                .map(|age| age >= max_age)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Shift `name.N` to `name.N+1`, move the active file to `name.1` and reopen
+    pub fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        if self.policy.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.policy.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        // All or nothing, so that a partial write cannot leave a line to
+        // be finished in the next file
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        if let Some(&last) = buf.last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A rotating log file shared between threads
+pub type SharedLogFile = Arc<Mutex<RotatingFile>>;
+
+/// Open a shared rotating log file
+pub fn open_shared<P: AsRef<Path>>(path: P, policy: RotationPolicy) -> std::io::Result<SharedLogFile> {
+    Ok(Arc::new(Mutex::new(RotatingFile::open(path, policy)?)))
+}
+
+/// Writer for Harbor's own log records
+///
+/// Records always go to stderr. Once a log file is attached they are
+/// copied there as well, so logging can be initialized before the app's
+/// configuration (and therefore its log directory) is known.
+#[derive(Debug, Clone, Default)]
+pub struct HarborLogWriter {
+    file: Arc<Mutex<Option<RotatingFile>>>,
+}
+
+impl HarborLogWriter {
+    /// Create a writer with no log file attached
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start copying records to `file`
+    pub fn attach(&self, file: RotatingFile) {
+        *self.file.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    }
+}
+
+impl Write for HarborLogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::io::stderr().write(buf)?;
+        if let Some(ref mut file) = *self.file.lock().unwrap_or_else(|e| e.into_inner()) {
+            // Losing a line in the log file must never break logging itself
+            let _ = file.write_all(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(ref mut file) = *self.file.lock().unwrap_or_else(|e| e.into_inner()) {
+            let _ = file.flush();
+        }
+        std::io::stderr().flush()
+    }
+}
+
+/// Format a timestamp as UTC RFC 3339 with millisecond precision
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Callback invoked for every captured line
+pub type LineObserver = Arc<dyn Fn(&LogLine) + Send + Sync>;
+
+/// Drains backend output streams and forwards each line
+#[derive(Clone)]
+pub struct LogCapture {
+    app: String,
+    backend: String,
+    level: LevelFilter,
+    buffer: LogBuffer,
+    file: Option<SharedLogFile>,
+    observer: Option<LineObserver>,
+}
+
+impl fmt::Debug for LogCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogCapture")
+            .field("app", &self.app)
+            .field("backend", &self.backend)
+            .field("level", &self.level)
+            .field("file", &self.file)
+            .field("observer", &self.observer.is_some())
+            .finish()
+    }
+}
+
+impl LogCapture {
+    /// Create a capture for the given app and backend
+    ///
+    /// Lines are forwarded to the `log` crate at `level`; `LevelFilter::Off`
+    /// disables forwarding but still records lines in `buffer`.
+    pub fn new(
+        app: impl Into<String>,
+        backend: impl Into<String>,
+        level: LevelFilter,
+        buffer: LogBuffer,
+    ) -> Self {
+        Self {
+            app: app.into(),
+            backend: backend.into(),
+            level,
+            buffer,
+            file: None,
+            observer: None,
+        }
+    }
+
+    /// Call `observer` with every captured line
+    pub fn with_observer(mut self, observer: LineObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Also append every line to `file`
+    pub fn with_file(mut self, file: SharedLogFile) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Get the buffer lines are recorded in
+    pub fn buffer(&self) -> &LogBuffer {
+        &self.buffer
+    }
+
+    /// Spawn a thread that drains `reader` until EOF
+    pub fn spawn<R>(&self, stream: LogStream, reader: R) -> std::io::Result<JoinHandle<()>>
+    where
+        R: Read + Send + 'static,
+    {
+        let capture = self.clone();
+        std::thread::Builder::new()
+            .name(format!("harbor-{}-{}", self.backend, stream))
+            .spawn(move || capture.drain(stream, reader))
+    }
+
+    /// Read `reader` line by line until EOF or error
+    fn drain<R: Read>(&self, stream: LogStream, reader: R) {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // Backends may emit non-UTF-8 output; keep what we can
+                    let text = String::from_utf8_lossy(&buf);
+                    self.record(stream, text.trim_end_matches(['\n', '\r']));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::debug!("Stopped reading backend {}: {}", stream, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Forward and buffer a single line
+    fn record(&self, stream: LogStream, text: &str) {
+        let line = LogLine {
+            timestamp: SystemTime::now(),
+            app: self.app.clone(),
+            backend: self.backend.clone(),
+            stream,
+            line: text.to_string(),
+        };
+
+        if let Some(level) = self.level.to_level() {
+            log::log!(target: OUTPUT_TARGET, level, "{}", line);
+        }
+
+        if let Some(ref file) = self.file {
+            // One write per line, so that rotation cannot split it
+            let entry = format!("{} {}\n", format_timestamp(line.timestamp), line);
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = file.write_all(entry.as_bytes());
+        }
+
+        if let Some(ref observer) = self.observer {
+            observer(&line);
+        }
+
+        self.buffer.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_buffer_evicts_oldest() {
+        let buffer = LogBuffer::new(2);
+        let capture = LogCapture::new("app", "web", LevelFilter::Off, buffer.clone());
+        capture.record(LogStream::Stdout, "one");
+        capture.record(LogStream::Stdout, "two");
+        capture.record(LogStream::Stderr, "three");
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].line, "two");
+        assert_eq!(lines[1].line, "three");
+        assert_eq!(lines[1].stream, LogStream::Stderr);
+        assert_eq!(buffer.recent(1)[0].line, "three");
+    }
+
+    #[test]
+    fn test_drain_tags_lines() {
+        let buffer = LogBuffer::new(10);
+        let capture = LogCapture::new("My App", "gunicorn", LevelFilter::Off, buffer.clone());
+        let input = Cursor::new(b"Booting worker\r\nListening\n\xffpartial".to_vec());
+        capture
+            .spawn(LogStream::Stderr, input)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].line, "Booting worker");
+        assert_eq!(lines[1].line, "Listening");
+        assert_eq!(lines[2].line, "\u{fffd}partial");
+        assert_eq!(lines[0].to_string(), "[My App/gunicorn:stderr] Booting worker");
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("harbor-logs-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let policy = RotationPolicy {
+            max_size: 10,
+            max_age: None,
+            keep: 2,
+        };
+        let mut file = RotatingFile::open(dir.join("backend.log"), policy).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fou", "rth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |p: PathBuf| std::fs::read_to_string(p).unwrap();
+        // A line written in pieces is not split across files
+        assert_eq!(read(dir.join("backend.log")), "third\nfourth\n");
+        assert_eq!(read(file.rotated_path(1)), "second\n");
+        assert_eq!(read(file.rotated_path(2)), "first\n");
+        assert!(!file.rotated_path(3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_210_096_007);
+        assert_eq!(format_timestamp(time), "2024-02-29T12:34:56.007Z");
+        assert_eq!(format_timestamp(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}
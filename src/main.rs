@@ -6,14 +6,20 @@
 //!
 //! Usage:
 //!   harbor <app.toml>              Run an app from config file
+//!   harbor                         Run ./app.toml, or [tool.harbor] in
+//!                                  ./pyproject.toml, or "harbor" in ./package.json
 //!   harbor --example hello-flask   Run a built-in example
 //!   harbor --help                  Show help
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use harbor::{BrowserConfig, HarborApp, HarborConfig, run_browser, is_browser_available};
+use harbor::health::BackendState;
+use harbor::config::LoadOptions;
+use harbor::logs::HarborLogWriter;
+use harbor::manifest::find_config;
 use log::{info, warn};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "harbor")]
@@ -41,6 +47,14 @@ struct Cli {
     #[arg(long)]
     print_url: bool,
 
+    /// Apply the [profile.<NAME>] tables of app.toml (default: $HARBOR_PROFILE)
+    #[arg(long, value_name = "NAME", global = true)]
+    profile: Option<String>,
+
+    /// Override a config value, e.g. --set frontend.width=1200 (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", global = true)]
+    set: Vec<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -59,25 +73,110 @@ enum Commands {
     Check {
         /// Path to configuration file
         config: PathBuf,
+
+        /// Treat unknown keys as errors
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Upgrade a configuration file to the current layout, keeping comments
+    Migrate {
+        /// Path to configuration file
+        config: PathBuf,
+
+        /// Print the upgraded file instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print a JSON Schema for app.toml, for editor validation and completion
+    Schema,
+    /// Inspect the resolved configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print every setting, defaults included, with where it came from
+    Show {
+        /// Path to configuration file (default: app.toml, pyproject.toml
+        /// or package.json in the current directory)
+        config: Option<PathBuf>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Toml)]
+        format: ConfigFormat,
+    },
+    /// Change a setting in the config file, keeping its comments
+    Set {
+        /// Key path, such as frontend.width or backends[0].env.PORT
+        key: String,
+
+        /// New value, as TOML; text that is not TOML is a string
+        value: String,
+
+        /// Config file to edit (default: app.toml or pyproject.toml in the
+        /// current directory)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+    /// Remove a setting from the config file, so its default applies
+    Unset {
+        /// Key path, such as frontend.width
+        key: String,
+
+        /// Config file to edit (default: app.toml or pyproject.toml in the
+        /// current directory)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Initialize logging
+    // Initialize logging; the app's log file is attached once config is loaded
+    let log_writer = HarborLogWriter::new();
     env_logger::Builder::from_env(
         env_logger::Env::default().default_filter_or(&cli.log_level),
     )
     .format_timestamp_millis()
+    .target(env_logger::Target::Pipe(Box::new(log_writer.clone())))
     .init();
 
+    let load_options = LoadOptions {
+        profile: cli.profile.clone(),
+        sets: cli.set.clone(),
+        ..LoadOptions::layered()
+    };
+
     // Handle subcommands
     if let Some(command) = cli.command {
         return match command {
             Commands::Init { name } => init_app(name),
             Commands::Examples => list_examples(),
-            Commands::Check { config } => check_config(&config),
+            Commands::Check { config, strict } => check_config(&config, &load_options, strict),
+            Commands::Schema => {
+                print!("{}", harbor::schema::schema_json());
+                Ok(())
+            }
+            Commands::Migrate { config, dry_run } => migrate_config(&config, dry_run),
+            Commands::Config { command: ConfigCommand::Show { config, format } } => {
+                show_config(config, &load_options, format)
+            }
+            Commands::Config { command: ConfigCommand::Set { key, value, file } } => {
+                edit_config(file, |editor| editor.set(&key, &value))
+            }
+            Commands::Config { command: ConfigCommand::Unset { key, file } } => {
+                edit_config(file, |editor| editor.unset(&key))
+            }
         };
     }
 
@@ -85,14 +184,14 @@ fn main() -> Result<()> {
     let config = if let Some(example_name) = cli.example {
         get_example_config(&example_name)?
     } else if let Some(config_path) = cli.config {
-        HarborConfig::load(&config_path)
+        HarborConfig::load_with(&config_path, &load_options)
             .with_context(|| format!("Failed to load config: {}", config_path.display()))?
     } else {
-        // Try to find app.toml in current directory
-        let default_path = PathBuf::from("app.toml");
-        if default_path.exists() {
-            HarborConfig::load(&default_path)
-                .with_context(|| "Failed to load app.toml")?
+        // Look for app.toml, or a project manifest with a Harbor section,
+        // in the current directory
+        if let Some(default_path) = find_config(Path::new("")) {
+            HarborConfig::load_with(&default_path, &load_options)
+                .with_context(|| format!("Failed to load {}", default_path.display()))?
         } else {
             eprintln!("Usage: harbor <app.toml>");
             eprintln!("       harbor --example hello-flask");
@@ -102,7 +201,16 @@ fn main() -> Result<()> {
     };
 
     // Create and run the app
-    let mut app = HarborApp::new(config);
+    let mut app = HarborApp::new(config)?;
+
+    match app.open_harbor_log() {
+        Some(Ok(file)) => {
+            info!("Writing logs to {}", file.path().display());
+            log_writer.attach(file);
+        }
+        Some(Err(e)) => warn!("Cannot open Harbor log file: {}", e),
+        None => {}
+    }
 
     info!("Starting Harbor app: {}", app.name());
 
@@ -111,16 +219,14 @@ fn main() -> Result<()> {
     if cli.print_url {
         println!("{}", run_config.url);
         // Keep backend running until interrupted
-        wait_for_interrupt();
-        return Ok(());
+        return wait_for_interrupt(&app);
     }
 
     if cli.backend_only {
-        info!("Backend running at socket: {}", app.socket_path());
+        info!("Backend running at socket: {}", app.socket_path().unwrap_or_default());
         info!("URL: {}", run_config.url);
         info!("Press Ctrl+C to stop");
-        wait_for_interrupt();
-        return Ok(());
+        return wait_for_interrupt(&app);
     }
 
     // Check if browser support is available
@@ -130,16 +236,15 @@ fn main() -> Result<()> {
         println!("=== Harbor App Ready (Backend Only) ===");
         println!("App:    {}", run_config.title);
         println!("URL:    {}", run_config.url);
-        println!("Socket: {}", app.socket_path());
+        println!("Socket: {}", app.socket_path().unwrap_or_default());
         println!();
         println!("Browser support is not enabled. To test the backend:");
-        println!("  curl --unix-socket {} http://localhost/", app.socket_path());
+        println!("  {}", curl_command(app.socket_path().unwrap_or_default()));
         println!();
         println!("To enable browser support, rebuild with: cargo build --features servo");
         println!();
         println!("Press Ctrl+C to stop the backend.");
-        wait_for_interrupt();
-        return Ok(());
+        return wait_for_interrupt(&app);
     }
 
     // Create browser configuration from run config
@@ -171,13 +276,13 @@ fn main() -> Result<()> {
             println!();
             println!("App:    {}", run_config.title);
             println!("URL:    {}", run_config.url);
-            println!("Socket: {}", app.socket_path());
+            println!("Socket: {}", app.socket_path().unwrap_or_default());
             println!();
             println!("To test the backend:");
-            println!("  curl --unix-socket {} http://localhost/", app.socket_path());
+            println!("  {}", curl_command(app.socket_path().unwrap_or_default()));
             println!();
             println!("Press Ctrl+C to stop the backend.");
-            wait_for_interrupt();
+            wait_for_interrupt(&app)?;
         }
     }
 
@@ -185,7 +290,16 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn wait_for_interrupt() {
+/// A curl command line that requests `/` over the backend socket
+fn curl_command(socket: &str) -> String {
+    match socket.strip_prefix('@') {
+        Some(name) => format!("curl --abstract-unix-socket {} http://localhost/", name),
+        None => format!("curl --unix-socket {} http://localhost/", socket),
+    }
+}
+
+/// Block until Ctrl+C or until the backend fails permanently
+fn wait_for_interrupt(app: &HarborApp) -> Result<()> {
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
 
@@ -198,8 +312,12 @@ fn wait_for_interrupt() {
     .expect("Error setting Ctrl-C handler");
 
     while running.load(Ordering::SeqCst) {
+        if let BackendState::Failed(reason) = app.backend_state() {
+            anyhow::bail!("Backend stopped: {}", reason);
+        }
         std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    Ok(())
 }
 
 fn init_app(name: Option<String>) -> Result<()> {
@@ -221,8 +339,8 @@ version = "0.1.0"
 [backend]
 # Command to start your backend server
 command = "python"
-args = ["-m", "flask", "run", "--host=unix:/tmp/{name_lower}.sock"]
-socket = "/tmp/{name_lower}.sock"
+args = ["-m", "flask", "run", "--host=unix:${{socket}}"]
+# socket = "auto"  # Default: a unique path under $XDG_RUNTIME_DIR/harbor/
 # workdir = "."  # Uncomment to set working directory
 
 # Environment variables for your backend
@@ -231,8 +349,8 @@ FLASK_APP = "app.py"
 FLASK_ENV = "development"
 
 [frontend]
-# Transport-aware URL (http::unix:// for Unix sockets)
-url = "http::unix///tmp/{name_lower}.sock/"
+# Transport-aware URL (http::unix// followed by the socket path)
+url = "http::unix//${{socket}}/"
 width = 1200
 height = 800
 # title = "{name}"  # Defaults to app.name
@@ -241,8 +359,7 @@ height = 800
 devtools = false
 log_level = "info"
 "#,
-        name = app_name,
-        name_lower = app_name.to_lowercase().replace(' ', "-")
+        name = app_name
     );
 
     let config_path = PathBuf::from("app.toml");
@@ -270,21 +387,94 @@ fn list_examples() -> Result<()> {
     Ok(())
 }
 
-fn check_config(config_path: &PathBuf) -> Result<()> {
-    let config = HarborConfig::load(config_path)
+fn check_config(config_path: &PathBuf, options: &LoadOptions, strict: bool) -> Result<()> {
+    let config = HarborConfig::load_with(config_path, options)
         .with_context(|| format!("Failed to load: {}", config_path.display()))?;
 
-    println!("Configuration valid!");
+    let diagnostics = if strict {
+        config.validate_strict()
+    } else {
+        config.validate()
+    };
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        anyhow::bail!("{} has {} error(s)", config_path.display(), errors);
+    }
+
+    if diagnostics.is_empty() {
+        println!("Configuration valid!");
+    } else {
+        println!("Configuration valid, with {} warning(s)", diagnostics.len());
+    }
     println!();
-    println!("App:     {} v{}", config.app.name, config.app.version);
-    println!("Backend: {} {:?}", config.backend.command, config.backend.args);
-    println!("Socket:  {}", config.backend.socket);
-    println!("URL:     {}", config.frontend.url);
-    println!("Window:  {}x{}", config.frontend.width, config.frontend.height);
+    print!("{}", config.to_annotated_toml()?);
 
     Ok(())
 }
 
+fn show_config(config_path: Option<PathBuf>, options: &LoadOptions, format: ConfigFormat) -> Result<()> {
+    let config_path = config_path
+        .or_else(|| find_config(Path::new("")))
+        .context("No app.toml, or pyproject.toml or package.json with a Harbor section, found")?;
+    let config = HarborConfig::load_with(&config_path, options)
+        .with_context(|| format!("Failed to load: {}", config_path.display()))?;
+
+    match format {
+        ConfigFormat::Toml => print!("{}", config.to_annotated_toml()?),
+        ConfigFormat::Json => print!("{}", config.to_annotated_json()?),
+    }
+    Ok(())
+}
+
+fn edit_config(
+    config_path: Option<PathBuf>,
+    edit: impl FnOnce(&mut harbor::edit::ConfigEditor) -> Result<()>,
+) -> Result<()> {
+    let config_path = config_path
+        .or_else(|| find_config(Path::new("")))
+        .context("No app.toml, or pyproject.toml with a [tool.harbor] table, found")?;
+    let mut editor = harbor::edit::ConfigEditor::open(&config_path)?;
+    edit(&mut editor)?;
+    editor.save()?;
+    println!("Updated {}", config_path.display());
+    Ok(())
+}
+
+fn migrate_config(config_path: &PathBuf, dry_run: bool) -> Result<()> {
+    let contents = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read: {}", config_path.display()))?;
+    let (migrated, changes) = harbor::migrate::migrate_file(config_path, &contents)
+        .with_context(|| format!("Failed to migrate: {}", config_path.display()))?;
+
+    if migrated == contents {
+        println!(
+            "{} is already at config_version {}",
+            config_path.display(),
+            harbor::migrate::CONFIG_VERSION
+        );
+        return Ok(());
+    }
+    for change in &changes {
+        eprintln!("{}: {}", change.key, change.message);
+    }
+
+    if dry_run {
+        print!("{}", migrated);
+    } else {
+        std::fs::write(config_path, migrated)
+            .with_context(|| format!("Failed to write: {}", config_path.display()))?;
+        println!(
+            "Upgraded {} to config_version {}",
+            config_path.display(),
+            harbor::migrate::CONFIG_VERSION
+        );
+    }
+    Ok(())
+}
+
 fn get_example_config(name: &str) -> Result<HarborConfig> {
     match name {
         "hello-flask" => {
@@ -298,8 +488,7 @@ description = "Simple Flask example for Harbor"
 
 [backend]
 command = "gunicorn"
-args = ["--bind", "unix:/tmp/hello-harbor.sock", "app:app"]
-socket = "/tmp/hello-harbor.sock"
+args = ["--bind", "unix:${socket}", "app:app"]
 workdir = "examples/hello-flask"
 
 [backend.env]
@@ -307,7 +496,7 @@ FLASK_ENV = "development"
 PYTHONUNBUFFERED = "1"
 
 [frontend]
-url = "http::unix///tmp/hello-harbor.sock/"
+url = "http::unix//${socket}/"
 width = 800
 height = 600
 title = "Hello Harbor!"
@@ -316,7 +505,7 @@ title = "Hello Harbor!"
 devtools = false
 log_level = "info"
 "#;
-            HarborConfig::from_str(toml).context("Failed to parse hello-flask example config")
+            HarborConfig::from_str(toml, "").context("Failed to parse hello-flask example config")
         }
         _ => {
             anyhow::bail!("Unknown example: {}. Run 'harbor examples' to see available examples.", name);
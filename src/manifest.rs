@@ -0,0 +1,207 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Configuration embedded in a project's own manifest
+//!
+//! Instead of app.toml, a project may keep its Harbor configuration in:
+//!
+//! - `pyproject.toml`, as a `[tool.harbor]` table
+//! - `package.json`, as a `"harbor"` object
+//!
+//! The manifest's project name and version (`[project]` in pyproject.toml,
+//! the top-level `name` and `version` in package.json) are the defaults of
+//! `app.name` and `app.version`.
+
+use crate::interpolate::join_key;
+use crate::layers::{Layer, Source};
+use anyhow::{anyhow, bail};
+use std::path::{Path, PathBuf};
+
+/// The dedicated Harbor config file
+pub const APP_TOML: &str = "app.toml";
+
+/// Python project manifest, read for `[tool.harbor]`
+pub const PYPROJECT_TOML: &str = "pyproject.toml";
+
+/// Node.js project manifest, read for `"harbor"`
+pub const PACKAGE_JSON: &str = "package.json";
+
+/// Find the config in `dir`: app.toml, or else a pyproject.toml or
+/// package.json that has a Harbor section
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    let app_toml = dir.join(APP_TOML);
+    if app_toml.is_file() {
+        return Some(app_toml);
+    }
+    [PYPROJECT_TOML, PACKAGE_JSON]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| {
+            let contents = std::fs::read_to_string(path).unwrap_or_default();
+            read(path, &contents).is_ok()
+        })
+}
+
+/// Read the Harbor config from `contents`, the file at `path`
+///
+/// A file named pyproject.toml or ending in `.json` is read as a manifest,
+/// and it is an error if it has no Harbor section. Anything else is a
+/// plain config file. Returns the config with the layer it was read as.
+pub fn read(path: &Path, contents: &str) -> anyhow::Result<(toml::Table, Layer)> {
+    let layer = Layer::from_file(Source::file(path), contents);
+
+    if path.file_name().is_some_and(|name| name == PYPROJECT_TOML) {
+        let mut manifest: toml::Table = toml::from_str(contents)?;
+        let harbor = manifest
+            .get_mut("tool")
+            .and_then(|tool| tool.as_table_mut())
+            .and_then(|tool| tool.remove("harbor"));
+        let Some(toml::Value::Table(mut harbor)) = harbor else {
+            bail!("{} has no [tool.harbor] table", path.display());
+        };
+        let project = manifest.get("project").and_then(toml::Value::as_table);
+        let layer = project_defaults(&mut harbor, project, "project", layer.within("tool.harbor"));
+        return Ok((harbor, layer));
+    }
+
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let toml::Value::Table(mut manifest) = parse_json(contents)? else {
+            bail!("{}: expected a JSON object", path.display());
+        };
+        let Some(toml::Value::Table(mut harbor)) = manifest.remove("harbor") else {
+            bail!("{} has no \"harbor\" object", path.display());
+        };
+        let layer = project_defaults(&mut harbor, Some(&manifest), "", Layer::new(Source::file(path)));
+        return Ok((harbor, layer));
+    }
+
+    Ok((toml::from_str(contents)?, layer))
+}
+
+/// Default `app.name` and `app.version` to the project's, found in
+/// `project` at `key` in the manifest
+///
+/// The scope of an npm package name is dropped: `@acme/reports` is the app
+/// `reports`.
+fn project_defaults(harbor: &mut toml::Table, project: Option<&toml::Table>, key: &str, mut layer: Layer) -> Layer {
+    let Some(project) = project else {
+        return layer;
+    };
+    for name in ["name", "version"] {
+        let Some(toml::Value::String(value)) = project.get(name) else {
+            continue;
+        };
+        let value = match value.strip_prefix('@').and_then(|scoped| scoped.split_once('/')) {
+            Some((_, package)) if name == "name" => package,
+            _ => value,
+        };
+        let app = harbor
+            .entry("app")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let Some(app) = app.as_table_mut().filter(|app| !app.contains_key(name)) {
+            app.insert(name.to_string(), toml::Value::String(value.to_string()));
+            layer = layer.with_line_from(&join_key("app", name), &join_key(key, name));
+        }
+    }
+    layer
+}
+
+/// Parse a JSON document into the equivalent TOML value
+///
+/// `null` members of objects are left out, as TOML has no null. Numbers
+/// with no fractional part, such as `1.5e3`, are integers.
+pub(crate) fn parse_json(input: &str) -> anyhow::Result<toml::Value> {
+    let value: serde_json::Value = serde_json::from_str(input)
+        .map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+    json_to_toml(value)?.ok_or_else(|| anyhow!("Invalid JSON: null is not supported here"))
+}
+
+/// Convert a JSON value to TOML; `None` is null
+fn json_to_toml(value: serde_json::Value) -> anyhow::Result<Option<toml::Value>> {
+    use serde_json::Value;
+
+    let value = match value {
+        Value::Null => return Ok(None),
+        Value::Bool(b) => toml::Value::Boolean(b),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => toml::Value::Integer(i),
+            (None, Some(f)) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => toml::Value::Integer(f as i64),
+            (None, f) => toml::Value::Float(f.unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => toml::Value::String(s),
+        Value::Array(items) => toml::Value::Array(
+            items
+                .into_iter()
+                .map(|item| json_to_toml(item)?.ok_or_else(|| anyhow!("Invalid JSON: null is not supported in arrays")))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Value::Object(members) => {
+            let mut table = toml::Table::new();
+            for (key, value) in members {
+                if let Some(value) = json_to_toml(value)? {
+                    table.insert(key, value);
+                }
+            }
+            toml::Value::Table(table)
+        }
+    };
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pyproject() {
+        let contents = r#"
+[project]
+name = "reports"
+version = "2.1.0"
+
+[tool.harbor.backend]
+command = "gunicorn"
+
+[tool.harbor.frontend]
+url = "http::unix//${socket}/"
+"#;
+        let (table, _) = read(Path::new("/srv/reports/pyproject.toml"), contents).unwrap();
+        assert_eq!(table["app"]["name"].as_str(), Some("reports"));
+        assert_eq!(table["app"]["version"].as_str(), Some("2.1.0"));
+        assert_eq!(table["backend"]["command"].as_str(), Some("gunicorn"));
+
+        let err = read(Path::new("pyproject.toml"), "[project]\nname = \"x\"\n").unwrap_err();
+        assert_eq!(err.to_string(), "pyproject.toml has no [tool.harbor] table");
+    }
+
+    #[test]
+    fn test_package_json() {
+        let contents = r#"{
+  "name": "dashboard",
+  "version": "1.0.0",
+  "private": true,
+  "harbor": {
+    "app": { "name": "Dashboard é", "icon": null },
+    "backend": { "command": "node", "args": ["server.js", "--port=0"], "startup_timeout": 15 },
+    "frontend": { "url": "http::unix//${socket}/", "width": 1.5e3, "title": "Dashboard \ud83d\udcca" }
+  }
+}"#;
+        let (table, _) = read(Path::new("package.json"), contents).unwrap();
+        assert_eq!(table["app"]["name"].as_str(), Some("Dashboard é"));
+        assert_eq!(table["app"]["version"].as_str(), Some("1.0.0"));
+        assert!(table["app"].get("icon").is_none());
+        assert_eq!(table["backend"]["args"][1].as_str(), Some("--port=0"));
+        assert_eq!(table["backend"]["startup_timeout"].as_integer(), Some(15));
+        assert_eq!(table["frontend"]["width"].as_integer(), Some(1500));
+        assert_eq!(table["frontend"]["title"].as_str(), Some("Dashboard \u{1F4CA}"));
+
+        let err = read(Path::new("package.json"), "{\n  \"harbor\": {,}\n}").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid JSON: key must be a string at line 2 column 14");
+
+        // The npm scope is not part of the app name
+        let contents = r#"{ "name": "@acme/reports", "harbor": { "backend": { "command": "node" } } }"#;
+        let (table, _) = read(Path::new("package.json"), contents).unwrap();
+        assert_eq!(table["app"]["name"].as_str(), Some("reports"));
+    }
+}
@@ -0,0 +1,325 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Upgrading older config layouts
+//!
+//! Every config file has a `config_version`; a file without one is
+//! version 1. When the layout changes, the version goes up and a migration
+//! rewriting the old layout into the new one is added to `MIGRATIONS`.
+//! Older files are upgraded in memory on load, with a deprecation warning
+//! for each change, and `harbor migrate` writes the upgrade back to the
+//! file.
+//!
+//! Migrations work on `toml_edit` documents so that rewriting a file keeps
+//! its comments and formatting.
+//!
+//! | Version | Change |
+//! |---------|--------|
+//! | 1 | Original layout |
+//! | 2 | `restart_on_crash = true`/`false` is `restart.policy = "always"`/`"never"` |
+
+use crate::interpolate::join_key;
+use crate::manifest::PYPROJECT_TOML;
+use crate::validate::Diagnostic;
+use anyhow::{anyhow, bail};
+use std::path::Path;
+use toml_edit::{Item, TableLike, Value};
+
+/// Key holding a config file's layout version
+pub const VERSION_KEY: &str = "config_version";
+
+/// The layout version this Harbor reads
+pub const CONFIG_VERSION: i64 = 2;
+
+/// One upgrade of the layout
+struct Migration {
+    /// Version of the layout after the migration
+    to: i64,
+    /// Rewrite a config root (the top level of a file or a profile) at
+    /// key path `key`, recording each change
+    apply: fn(&mut dyn TableLike, &str, &mut Vec<Diagnostic>),
+}
+
+/// Every migration, oldest first
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 2,
+    apply: restart_policy,
+}];
+
+/// Upgrade a config, the top level of a file or `[tool.harbor]`, to the
+/// current version
+///
+/// Profiles are upgraded along with the rest. `config_version` is set to
+/// the current version. Returns a deprecation warning for each change.
+pub fn migrate_document(root: &mut dyn TableLike) -> anyhow::Result<Vec<Diagnostic>> {
+    let version = match root.get(VERSION_KEY) {
+        None => 1,
+        Some(item) => match item.as_integer() {
+            Some(version) if (1..=CONFIG_VERSION).contains(&version) => version,
+            Some(version) if version > CONFIG_VERSION => bail!(
+                "{}: {} is newer than this Harbor supports ({}); upgrade Harbor",
+                VERSION_KEY,
+                version,
+                CONFIG_VERSION
+            ),
+            _ => bail!("{}: expected a version from 1 to {}", VERSION_KEY, CONFIG_VERSION),
+        },
+    };
+
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.to > version) {
+        (migration.apply)(root, "", &mut changes);
+        if let Some(profiles) = root.get_mut("profile").and_then(Item::as_table_like_mut) {
+            for (name, profile) in profiles.iter_mut() {
+                if let Some(profile) = profile.as_table_like_mut() {
+                    let key = join_key("profile", &name);
+                    (migration.apply)(profile, &key, &mut changes);
+                }
+            }
+        }
+    }
+
+    if version < CONFIG_VERSION {
+        match root.get_mut(VERSION_KEY) {
+            Some(item) => *item = toml_edit::value(CONFIG_VERSION),
+            None => {
+                // Below the comments heading the file, not above them
+                let header = take_header(root);
+                root.insert(VERSION_KEY, toml_edit::value(CONFIG_VERSION));
+                if let (Some(header), Some(mut key)) = (header, root.key_mut(VERSION_KEY)) {
+                    key.leaf_decor_mut().set_prefix(header);
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Take the comments heading a file whose first line is a table header
+///
+/// A new top-level key is written above the first table, and so above the
+/// comments before it. Those up to the last blank line are the file's
+/// header and move to the key; the rest stay with the table. Returns
+/// `None` if `root` has top-level keys, which the new key goes after.
+fn take_header(root: &mut dyn TableLike) -> Option<String> {
+    if root.iter().any(|(_, item)| item.is_value()) {
+        return None;
+    }
+    let decor = root.iter_mut().find_map(|(_, item)| first_table(item))?.decor_mut();
+    let prefix = decor.prefix().and_then(|p| p.as_str())?.to_string();
+    if !prefix.contains('#') {
+        return None;
+    }
+    let split = prefix.rfind("\n\n").map_or(prefix.len(), |i| i + 1);
+    decor.set_prefix(format!("\n{}", prefix[split..].trim_start_matches('\n')));
+    Some(prefix[..split].to_string())
+}
+
+/// The table in `item` whose header is the first line of the file
+fn first_table(item: &mut Item) -> Option<&mut toml_edit::Table> {
+    match item {
+        Item::Table(table) => {
+            if table.position() == Some(1) && !table.is_implicit() {
+                return Some(table);
+            }
+            table.iter_mut().find_map(|(_, item)| first_table(item))
+        }
+        Item::ArrayOfTables(tables) => tables.get_mut(0).filter(|t| t.position() == Some(1)),
+        _ => None,
+    }
+}
+
+/// Upgrade a parsed config file in memory
+///
+/// Like `migrate_document`, but `config_version` is only set if the file
+/// had one.
+pub fn migrate_table(table: &mut toml::Table) -> anyhow::Result<Vec<Diagnostic>> {
+    if table.get(VERSION_KEY).and_then(toml::Value::as_integer) == Some(CONFIG_VERSION) {
+        return Ok(Vec::new());
+    }
+
+    let mut document: toml_edit::DocumentMut = toml::to_string(table)?.parse()?;
+    let changes = migrate_document(document.as_table_mut())?;
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    let had_version = table.contains_key(VERSION_KEY);
+    *table = toml::from_str(&document.to_string())?;
+    if !had_version {
+        table.remove(VERSION_KEY);
+    }
+    Ok(changes)
+}
+
+/// Upgrade the config file at `path`, whose contents are `contents`,
+/// keeping its comments and formatting
+///
+/// In a pyproject.toml, `[tool.harbor]` is upgraded. Returns the new
+/// contents and the changes made.
+pub fn migrate_file(path: &Path, contents: &str) -> anyhow::Result<(String, Vec<Diagnostic>)> {
+    if path.extension().is_some_and(|ext| ext == "json") {
+        bail!("{}: only TOML files can be migrated", path.display());
+    }
+    let mut document: toml_edit::DocumentMut = contents.parse()?;
+
+    let root = if path.file_name().is_some_and(|name| name == PYPROJECT_TOML) {
+        document
+            .get_mut("tool")
+            .and_then(|tool| tool.get_mut("harbor"))
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| anyhow!("{} has no [tool.harbor] table", path.display()))?
+    } else {
+        document.as_table_mut()
+    };
+    let changes = migrate_document(root)?;
+    Ok((document.to_string(), changes))
+}
+
+/// Every backend table in a config root, with its key path
+fn backends<'a>(root: &'a mut dyn TableLike, key: &str) -> Vec<(String, &'a mut dyn TableLike)> {
+    let mut found = Vec::new();
+    for (name, item) in root.iter_mut() {
+        let path = join_key(key, &name);
+        match (name.get(), item) {
+            ("backend", item) => found.extend(item.as_table_like_mut().map(|t| (path, t))),
+            ("backends", Item::ArrayOfTables(tables)) => {
+                for (i, table) in tables.iter_mut().enumerate() {
+                    found.push((format!("{}[{}]", path, i), table as &mut dyn TableLike));
+                }
+            }
+            ("backends", Item::Value(Value::Array(items))) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    if let Value::InlineTable(table) = item {
+                        found.push((format!("{}[{}]", path, i), table as &mut dyn TableLike));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+/// Version 2: `restart_on_crash` is replaced by `restart.policy`
+fn restart_policy(root: &mut dyn TableLike, key: &str, changes: &mut Vec<Diagnostic>) {
+    for (path, backend) in backends(root, key) {
+        let Some(on_crash) = backend.get("restart_on_crash").and_then(Item::as_bool) else {
+            continue;
+        };
+        // Comments on the old key move to the new one
+        let comments = backend
+            .key("restart_on_crash")
+            .and_then(|k| k.leaf_decor().prefix())
+            .cloned();
+        let trailing = backend.remove("restart_on_crash").and_then(|old| match old {
+            Item::Value(value) => value.decor().suffix().cloned(),
+            _ => None,
+        });
+
+        let policy = if on_crash { "always" } else { "never" };
+        let restart = backend.entry("restart").or_insert_with(|| {
+            let mut restart = toml_edit::InlineTable::new();
+            if let Some(trailing) = trailing {
+                restart.decor_mut().set_suffix(trailing);
+            }
+            Item::Value(Value::InlineTable(restart))
+        });
+        let Some(restart) = restart.as_table_like_mut() else {
+            continue;
+        };
+        let message = if restart.contains_key("policy") {
+            "deprecated and overridden by restart.policy; removed".to_string()
+        } else {
+            restart.insert("policy", toml_edit::value(policy));
+            format!("deprecated, use restart.policy = \"{}\"", policy)
+        };
+        if let (Some(comments), Some(mut new_key)) = (comments, backend.key_mut("restart")) {
+            if new_key.leaf_decor().prefix().is_none_or(|p| p.as_str() == Some("")) {
+                new_key.leaf_decor_mut().set_prefix(comments);
+            }
+        }
+        changes.push(Diagnostic::warning(join_key(&path, "restart_on_crash"), message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_document() {
+        let input = r#"# My app
+[app]
+name = "Old"
+
+[backend]
+command = "gunicorn"
+# Keep it up
+restart_on_crash = true  # even after a clean exit
+
+[[backends]]
+name = "worker"
+restart_on_crash = false
+
+[backends.restart]
+max_restarts = 2
+
+[profile.dev.backend]
+restart_on_crash = false
+"#;
+        let mut document: toml_edit::DocumentMut = input.parse().unwrap();
+        let changes = migrate_document(document.as_table_mut()).unwrap();
+        let keys: Vec<&str> = changes.iter().map(|d| d.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "backend.restart_on_crash",
+                "backends[0].restart_on_crash",
+                "profile.dev.backend.restart_on_crash"
+            ]
+        );
+        assert_eq!(
+            document.to_string(),
+            r#"# My app
+config_version = 2
+
+[app]
+name = "Old"
+
+[backend]
+command = "gunicorn"
+# Keep it up
+restart = { policy = "always" }  # even after a clean exit
+
+[[backends]]
+name = "worker"
+
+[backends.restart]
+max_restarts = 2
+policy = "never"
+
+[profile.dev.backend]
+restart = { policy = "never" }
+"#
+        );
+
+        // Already current: nothing to do
+        assert_eq!(migrate_document(document.as_table_mut()).unwrap(), vec![]);
+
+        // The file header moves above config_version; the table's own
+        // comment stays with it
+        let mut document: toml_edit::DocumentMut = "# My app\n\n# The backend\n[backend]\ncommand = \"app\"\n"
+            .parse()
+            .unwrap();
+        migrate_document(document.as_table_mut()).unwrap();
+        assert_eq!(
+            document.to_string(),
+            "# My app\nconfig_version = 2\n\n# The backend\n[backend]\ncommand = \"app\"\n"
+        );
+
+        let mut future: toml_edit::DocumentMut = "config_version = 99".parse().unwrap();
+        assert!(migrate_document(future.as_table_mut()).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Backend readiness strategies
+//!
+//! A socket that accepts connections is not necessarily ready to serve:
+//! gunicorn binds before the app has finished importing. After the socket
+//! connects, the backend manager keeps polling the configured strategy
+//! until it reports ready:
+//!
+//! - `http`: an HTTP request over the socket returns the expected response
+//! - `log-line`: a line of backend output matches a regex
+//! - `file`: the backend creates a marker file
+//! - `notify`: the backend sends `READY=1` on `$NOTIFY_SOCKET` (sd_notify)
+
+use crate::backend::BackendError;
+use crate::config::{
+    notify_socket_path, FileReadinessConfig, HttpReadinessConfig, LogLineReadinessConfig,
+    ReadinessConfig,
+};
+use crate::http;
+use crate::logs::LineObserver;
+use crate::socket::BackendSocket;
+use regex::Regex;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default delay between readiness checks
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Outcome of a single probe attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    /// The backend is ready
+    Ready,
+    /// The backend is not ready yet, with a description of why
+    NotReady(String),
+}
+
+impl ProbeResult {
+    /// Whether the probe passed
+    pub fn is_ready(&self) -> bool {
+        matches!(self, ProbeResult::Ready)
+    }
+}
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeResult::Ready => write!(f, "ready"),
+            ProbeResult::NotReady(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A way of deciding that a backend is ready to serve
+pub trait ReadinessStrategy: Send {
+    /// Short name used in log messages
+    fn name(&self) -> &'static str;
+
+    /// Adjust the backend command before it is spawned
+    fn prepare(&mut self, _cmd: &mut Command) -> Result<(), BackendError> {
+        Ok(())
+    }
+
+    /// Observer for captured output lines, if the strategy needs one
+    fn observer(&self) -> Option<LineObserver> {
+        None
+    }
+
+    /// Check readiness once
+    fn check(&mut self, socket: &BackendSocket, timeout: Duration) -> ProbeResult;
+
+    /// Delay between checks
+    fn interval(&self) -> Duration {
+        DEFAULT_INTERVAL
+    }
+
+    /// Error reported when the check has not passed within the startup timeout
+    fn timeout_error(&self, timeout: u64, last: &ProbeResult) -> BackendError;
+}
+
+/// Build the strategy for a readiness configuration
+pub fn from_config(
+    config: &ReadinessConfig,
+    app_name: &str,
+    backend_name: &str,
+) -> Result<Box<dyn ReadinessStrategy>, BackendError> {
+    Ok(match config {
+        ReadinessConfig::Http(c) => Box::new(HttpProbe::new(c.clone())),
+        ReadinessConfig::LogLine(c) => Box::new(LogLineWatch::new(c)?),
+        ReadinessConfig::File(c) => Box::new(FileMarker::new(c)),
+        ReadinessConfig::Notify(_) => Box::new(NotifyListener::new(notify_socket_path(app_name, backend_name))),
+    })
+}
+
+/// HTTP request sent over the backend socket to check readiness
+#[derive(Debug, Clone)]
+pub struct HttpProbe {
+    config: HttpReadinessConfig,
+}
+
+impl HttpProbe {
+    /// Create a probe from configuration
+    pub fn new(config: HttpReadinessConfig) -> Self {
+        Self { config }
+    }
+
+    /// Send the probe request once and check the response
+    pub fn probe(&self, socket: &BackendSocket, timeout: Duration) -> ProbeResult {
+        match http::request(socket, &self.config.method, &self.config.path, None, timeout) {
+            Ok(response) => self.evaluate(&response),
+            Err(e) => ProbeResult::NotReady(format!(
+                "{} {}: {}",
+                self.config.method, self.config.path, e
+            )),
+        }
+    }
+
+    /// Check a response against the expected status and body
+    fn evaluate(&self, response: &http::HttpResponse) -> ProbeResult {
+        if response.status != self.config.status {
+            return ProbeResult::NotReady(format!(
+                "{} {} returned {} (expected {})",
+                self.config.method, self.config.path, response.status, self.config.status
+            ));
+        }
+
+        if let Some(ref needle) = self.config.body_contains {
+            if !response.body.contains(needle.as_str()) {
+                return ProbeResult::NotReady(format!(
+                    "{} {} returned {} but body does not contain {:?}",
+                    self.config.method, self.config.path, response.status, needle
+                ));
+            }
+        }
+
+        ProbeResult::Ready
+    }
+}
+
+impl ReadinessStrategy for HttpProbe {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn check(&mut self, socket: &BackendSocket, timeout: Duration) -> ProbeResult {
+        self.probe(socket, timeout)
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.config.interval_ms)
+    }
+
+    fn timeout_error(&self, timeout: u64, last: &ProbeResult) -> BackendError {
+        BackendError::HttpProbeTimeout {
+            timeout,
+            last: last.to_string(),
+        }
+    }
+}
+
+/// Waits for a line of backend output matching a pattern
+#[derive(Debug)]
+pub struct LogLineWatch {
+    pattern: Regex,
+    matched: Arc<AtomicBool>,
+}
+
+impl LogLineWatch {
+    /// Create a watch from configuration
+    pub fn new(config: &LogLineReadinessConfig) -> Result<Self, BackendError> {
+        let pattern = Regex::new(&config.pattern).map_err(|e| {
+            BackendError::StartFailed(format!("Invalid readiness pattern: {}", e))
+        })?;
+        Ok(Self {
+            pattern,
+            matched: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+impl ReadinessStrategy for LogLineWatch {
+    fn name(&self) -> &'static str {
+        "log-line"
+    }
+
+    fn observer(&self) -> Option<LineObserver> {
+        let pattern = self.pattern.clone();
+        let matched = self.matched.clone();
+        Some(Arc::new(move |line| {
+            if !matched.load(Ordering::Relaxed) && pattern.is_match(&line.line) {
+                matched.store(true, Ordering::Relaxed);
+            }
+        }))
+    }
+
+    fn check(&mut self, _socket: &BackendSocket, _timeout: Duration) -> ProbeResult {
+        if self.matched.load(Ordering::Relaxed) {
+            ProbeResult::Ready
+        } else {
+            ProbeResult::NotReady(format!("no output line matched {:?}", self.pattern.as_str()))
+        }
+    }
+
+    fn timeout_error(&self, timeout: u64, _last: &ProbeResult) -> BackendError {
+        BackendError::LogLineTimeout {
+            timeout,
+            pattern: self.pattern.as_str().to_string(),
+        }
+    }
+}
+
+/// Waits for the backend to create a marker file
+#[derive(Debug)]
+pub struct FileMarker {
+    path: PathBuf,
+}
+
+impl FileMarker {
+    /// Create a marker check from configuration
+    pub fn new(config: &FileReadinessConfig) -> Self {
+        Self {
+            path: config.path.clone(),
+        }
+    }
+}
+
+impl ReadinessStrategy for FileMarker {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn prepare(&mut self, _cmd: &mut Command) -> Result<(), BackendError> {
+        // A marker left over from a previous run would make us ready too early
+        if self.path.is_file() {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn check(&mut self, _socket: &BackendSocket, _timeout: Duration) -> ProbeResult {
+        if self.path.exists() {
+            ProbeResult::Ready
+        } else {
+            ProbeResult::NotReady(format!("{} does not exist", self.path.display()))
+        }
+    }
+
+    fn timeout_error(&self, timeout: u64, _last: &ProbeResult) -> BackendError {
+        BackendError::FileMarkerTimeout {
+            timeout,
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// Receives sd_notify messages from the backend
+///
+/// A datagram socket is bound before spawn and passed to the backend in
+/// `NOTIFY_SOCKET`. The socket file is kept in the app's private runtime
+/// directory, like `auto` backend sockets. The backend is ready once it sends `READY=1`; any
+/// `STATUS=` text it sends is kept for error reporting.
+#[derive(Debug)]
+pub struct NotifyListener {
+    #[cfg(unix)]
+    socket: Option<std::os::unix::net::UnixDatagram>,
+    path: PathBuf,
+    status: Option<String>,
+    ready: bool,
+}
+
+impl NotifyListener {
+    /// Create a listener for a socket at `path`; it is bound in `prepare`
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            #[cfg(unix)]
+            socket: None,
+            path,
+            status: None,
+            ready: false,
+        }
+    }
+
+    /// Apply one notify message
+    fn handle_message(&mut self, message: &str) {
+        for line in message.lines() {
+            if line == "READY=1" {
+                self.ready = true;
+            } else if let Some(status) = line.strip_prefix("STATUS=") {
+                self.status = Some(status.to_string());
+            }
+        }
+    }
+}
+
+impl ReadinessStrategy for NotifyListener {
+    fn name(&self) -> &'static str {
+        "notify"
+    }
+
+    #[cfg(unix)]
+    fn prepare(&mut self, cmd: &mut Command) -> Result<(), BackendError> {
+        let address = BackendSocket::Path(self.path.clone());
+        address.create_dir()?;
+        address.check_dir()?;
+        address.remove_stale()?;
+
+        let socket = address.bind_datagram()?;
+        socket.set_nonblocking(true)?;
+
+        cmd.env("NOTIFY_SOCKET", &self.path);
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn prepare(&mut self, _cmd: &mut Command) -> Result<(), BackendError> {
+        Err(BackendError::StartFailed(
+            "sd_notify readiness requires Unix sockets".to_string(),
+        ))
+    }
+
+    fn check(&mut self, _socket: &BackendSocket, _timeout: Duration) -> ProbeResult {
+        #[cfg(unix)]
+        {
+            let mut buf = [0u8; 4096];
+            while let Some(Ok(n)) = self.socket.as_ref().map(|s| s.recv(&mut buf)) {
+                let message = String::from_utf8_lossy(&buf[..n]).into_owned();
+                self.handle_message(&message);
+            }
+        }
+
+        if self.ready {
+            ProbeResult::Ready
+        } else {
+            match self.status {
+                Some(ref status) => ProbeResult::NotReady(format!("status: {}", status)),
+                None => ProbeResult::NotReady("no READY=1 received".to_string()),
+            }
+        }
+    }
+
+    fn timeout_error(&self, timeout: u64, last: &ProbeResult) -> BackendError {
+        BackendError::NotifyTimeout {
+            timeout,
+            last: last.to_string(),
+        }
+    }
+}
+
+impl Drop for NotifyListener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.socket.is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{LogLine, LogStream};
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_log_line_watch() {
+        let config = LogLineReadinessConfig {
+            pattern: r"Listening at: unix:\S+".to_string(),
+        };
+        let mut watch = LogLineWatch::new(&config).unwrap();
+        let observer = watch.observer().unwrap();
+        let line = |text: &str| LogLine {
+            timestamp: SystemTime::now(),
+            app: "app".to_string(),
+            backend: "gunicorn".to_string(),
+            stream: LogStream::Stderr,
+            line: text.to_string(),
+        };
+
+        observer(&line("Starting gunicorn 21.2.0"));
+        assert!(!watch.check(&BackendSocket::parse("/unused"), DEFAULT_INTERVAL).is_ready());
+
+        observer(&line("Listening at: unix:/tmp/app.sock (42)"));
+        assert!(watch.check(&BackendSocket::parse("/unused"), DEFAULT_INTERVAL).is_ready());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let config = LogLineReadinessConfig {
+            pattern: "(unclosed".to_string(),
+        };
+        assert!(LogLineWatch::new(&config).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_listener() {
+        use std::os::unix::net::UnixDatagram;
+
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("harbor-notify-test-{}", std::process::id()));
+        let mut listener = NotifyListener::new(dir.join("api.notify"));
+        let mut cmd = Command::new("true");
+        listener.prepare(&mut cmd).unwrap();
+
+        let path = cmd
+            .get_envs()
+            .find(|(k, _)| *k == "NOTIFY_SOCKET")
+            .and_then(|(_, v)| v)
+            .map(PathBuf::from)
+            .unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+
+        // Only the current user can reach the socket
+        let mode = |p: &PathBuf| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        client.send_to(b"STATUS=Loading models", &path).unwrap();
+        let result = listener.check(&BackendSocket::parse("/unused"), DEFAULT_INTERVAL);
+        assert_eq!(result, ProbeResult::NotReady("status: Loading models".to_string()));
+
+        client.send_to(b"READY=1\nSTATUS=Serving", &path).unwrap();
+        assert!(listener.check(&BackendSocket::parse("/unused"), DEFAULT_INTERVAL).is_ready());
+
+        drop(listener);
+        assert!(!path.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
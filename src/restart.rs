@@ -0,0 +1,222 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Backend restart policy
+//!
+//! Decides whether a failed backend is restarted, how long to wait first
+//! (exponential backoff with jitter), and when repeated failures amount to
+//! a crash loop that should stop restarting altogether.
+
+use crate::backend::BackendError;
+use crate::config::{RestartConfig, RestartPolicy};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// Number of recent failures reported in a crash-loop error
+const REPORTED_FAILURES: usize = 5;
+
+/// Why a backend needs restarting
+#[derive(Debug, Clone)]
+pub enum Failure {
+    /// The process exited
+    Exited(ExitStatus),
+    /// The process is alive but liveness checks keep failing
+    Unhealthy(String),
+    /// A restart attempt failed to bring the backend up
+    StartFailed(String),
+}
+
+impl Failure {
+    /// Whether this was a clean, successful exit
+    pub fn is_clean_exit(&self) -> bool {
+        matches!(self, Failure::Exited(status) if status.success())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Exited(status) => write!(f, "process exited with {}", status),
+            Failure::Unhealthy(reason) => write!(f, "unhealthy: {}", reason),
+            Failure::StartFailed(reason) => write!(f, "start failed: {}", reason),
+        }
+    }
+}
+
+/// What to do about a failure
+#[derive(Debug)]
+pub enum RestartDecision {
+    /// Restart after waiting this long
+    Restart(Duration),
+    /// The policy says not to restart
+    Stop,
+    /// Too many restarts within the window
+    GiveUp(BackendError),
+}
+
+/// Tracks recent restarts to apply backoff and crash-loop limits
+#[derive(Debug)]
+pub struct RestartTracker {
+    policy: RestartPolicy,
+    config: RestartConfig,
+    restarts: VecDeque<Instant>,
+    failures: VecDeque<String>,
+    attempt: u32,
+}
+
+impl RestartTracker {
+    /// Create a tracker for the given policy and limits
+    pub fn new(policy: RestartPolicy, config: RestartConfig) -> Self {
+        Self {
+            policy,
+            config,
+            restarts: VecDeque::new(),
+            failures: VecDeque::new(),
+            attempt: 0,
+        }
+    }
+
+    /// Get the restart policy
+    pub fn policy(&self) -> RestartPolicy {
+        self.policy
+    }
+
+    /// Record a failure and decide whether to restart
+    pub fn decide(&mut self, failure: &Failure) -> RestartDecision {
+        self.decide_at(failure, Instant::now())
+    }
+
+    fn decide_at(&mut self, failure: &Failure, now: Instant) -> RestartDecision {
+        match self.policy {
+            RestartPolicy::Never => return RestartDecision::Stop,
+            RestartPolicy::OnFailure if failure.is_clean_exit() => return RestartDecision::Stop,
+            _ => {}
+        }
+
+        self.failures.push_back(failure.to_string());
+        while self.failures.len() > REPORTED_FAILURES {
+            self.failures.pop_front();
+        }
+
+        // Forget restarts outside the window; a backend that stayed up for a
+        // whole window starts backing off from scratch
+        let window = Duration::from_secs(self.config.window_secs);
+        while let Some(&oldest) = self.restarts.front() {
+            if now.duration_since(oldest) < window {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        if self.restarts.is_empty() {
+            self.attempt = 0;
+        }
+
+        if self.config.max_restarts > 0 && self.restarts.len() >= self.config.max_restarts as usize {
+            return RestartDecision::GiveUp(BackendError::CrashLoop {
+                restarts: self.restarts.len(),
+                window: self.config.window_secs,
+                failures: self.failures.iter().cloned().collect(),
+            });
+        }
+
+        let delay = self.backoff(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        self.restarts.push_back(now);
+        RestartDecision::Restart(delay)
+    }
+
+    /// Backoff before restart number `attempt`, with jitter
+    ///
+    /// The base delay doubles per attempt up to `backoff_max_ms`; the actual
+    /// delay is picked uniformly from the upper half of the base delay so
+    /// restarts of several backends don't line up.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = self
+            .config
+            .backoff_initial_ms
+            .saturating_mul(1u64 << attempt.min(32))
+            .min(self.config.backoff_max_ms);
+        let half = base / 2;
+        let jitter = if half > 0 { random() % (half + 1) } else { 0 };
+        Duration::from_millis(base - half + jitter)
+    }
+}
+
+/// A random number from the standard library's hash seed
+fn random() -> u64 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn limits(max_restarts: u32) -> RestartConfig {
+        RestartConfig {
+            policy: None,
+            max_restarts,
+            window_secs: 60,
+            backoff_initial_ms: 100,
+            backoff_max_ms: 1000,
+        }
+    }
+
+    fn exit(code: i32) -> Failure {
+        Failure::Exited(ExitStatus::from_raw(code << 8))
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut never = RestartTracker::new(RestartPolicy::Never, limits(5));
+        assert!(matches!(never.decide(&exit(1)), RestartDecision::Stop));
+
+        let mut on_failure = RestartTracker::new(RestartPolicy::OnFailure, limits(5));
+        assert!(matches!(on_failure.decide(&exit(0)), RestartDecision::Stop));
+        assert!(matches!(on_failure.decide(&exit(1)), RestartDecision::Restart(_)));
+
+        let mut always = RestartTracker::new(RestartPolicy::Always, limits(5));
+        assert!(matches!(always.decide(&exit(0)), RestartDecision::Restart(_)));
+    }
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let tracker = RestartTracker::new(RestartPolicy::Always, limits(0));
+        for (attempt, base) in [(0, 100), (1, 200), (2, 400), (3, 800), (4, 1000), (40, 1000)] {
+            let delay = tracker.backoff(attempt).as_millis() as u64;
+            assert!(delay >= base / 2 && delay <= base, "attempt {}: {}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let mut tracker = RestartTracker::new(RestartPolicy::OnFailure, limits(2));
+        let start = Instant::now();
+
+        assert!(matches!(tracker.decide_at(&exit(1), start), RestartDecision::Restart(_)));
+        assert!(matches!(tracker.decide_at(&exit(2), start), RestartDecision::Restart(_)));
+        match tracker.decide_at(&exit(3), start) {
+            RestartDecision::GiveUp(BackendError::CrashLoop { restarts, failures, .. }) => {
+                assert_eq!(restarts, 2);
+                assert_eq!(failures.len(), 3);
+                assert!(failures[2].contains("exit status: 3"));
+            }
+            other => panic!("expected crash loop, got {:?}", other),
+        }
+
+        // Once the window has passed the backend may restart again
+        let later = start + Duration::from_secs(61);
+        assert!(matches!(tracker.decide_at(&exit(4), later), RestartDecision::Restart(_)));
+    }
+}
@@ -0,0 +1,776 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! JSON Schema for app.toml
+//!
+//! `harbor schema` prints a JSON Schema that editors such as taplo and
+//! Even Better TOML use to validate and complete app.toml. As much as
+//! possible is taken from the config structs themselves so that it cannot
+//! drift: field names and enum variants from serde, and defaults from
+//! deserializing a section with only its required keys. Only the JSON type
+//! and description of each field are written out here, and the tests check
+//! that every field has both.
+
+use crate::config::{
+    AppConfig, BackendConfig, FileReadinessConfig, FrontendConfig, HarborConfig, HealthConfig,
+    HttpReadinessConfig, LogLineReadinessConfig, LoggingConfig, RestartConfig, RestartPolicy,
+    SettingsConfig, StopSignal,
+};
+use crate::interpolate::join_key;
+use crate::keys::{field_names, suggestion, variant_names};
+use crate::layers::{format_key_path, Segment, EXTENDS_KEY};
+use crate::migrate::{CONFIG_VERSION, VERSION_KEY};
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+/// Log levels accepted by `settings.log_level` and `backend.log_level`, as
+/// `log::LevelFilter` parses them
+const LOG_LEVELS: &[&str] = &["off", "trace", "debug", "info", "warn", "error"];
+
+/// Description of `[backend.readiness]`
+const READINESS_DESCRIPTION: &str = "Readiness strategy configuration";
+
+/// Readiness strategies: the `type` of each, and the section of its keys
+const READINESS_TYPES: &[(&str, &str)] = &[
+    ("http", "HttpReadinessConfig"),
+    ("log-line", "LogLineReadinessConfig"),
+    ("file", "FileReadinessConfig"),
+    ("notify", "NotifyReadinessConfig"),
+];
+
+/// The JSON type of a field
+enum Type {
+    String,
+    Integer,
+    Boolean,
+    /// An array of strings
+    Strings,
+    /// A table of strings with any keys
+    StringMap,
+    /// A `[width, height]` pair
+    Size,
+    /// One of the given strings
+    Enum(Vec<&'static str>),
+    /// Another section, by definition name
+    Ref(&'static str),
+    /// An array of another section
+    RefArray(&'static str),
+}
+
+impl Type {
+    fn to_schema(&self) -> toml::Table {
+        let mut schema = toml::Table::new();
+        let mut set = |key: &str, value: toml::Value| {
+            schema.insert(key.to_string(), value);
+        };
+        let string = |s: &str| toml::Value::String(s.to_string());
+        let reference = |name: &str| {
+            let mut schema = toml::Table::new();
+            schema.insert("$ref".to_string(), string(&format!("#/definitions/{}", name)));
+            toml::Value::Table(schema)
+        };
+        let of_type = |ty: &str| {
+            let mut schema = toml::Table::new();
+            schema.insert("type".to_string(), string(ty));
+            toml::Value::Table(schema)
+        };
+
+        match self {
+            Type::String => set("type", string("string")),
+            Type::Integer => {
+                set("type", string("integer"));
+                set("minimum", toml::Value::Integer(0));
+            }
+            Type::Boolean => set("type", string("boolean")),
+            Type::Strings => {
+                set("type", string("array"));
+                set("items", of_type("string"));
+            }
+            Type::StringMap => {
+                set("type", string("object"));
+                set("additionalProperties", of_type("string"));
+            }
+            Type::Size => {
+                let mut dimension = toml::Table::new();
+                dimension.insert("type".to_string(), string("integer"));
+                dimension.insert("minimum".to_string(), toml::Value::Integer(1));
+                set("type", string("array"));
+                set("items", toml::Value::Table(dimension));
+                set("minItems", toml::Value::Integer(2));
+                set("maxItems", toml::Value::Integer(2));
+            }
+            Type::Enum(values) => {
+                set("type", string("string"));
+                set("enum", toml::Value::Array(values.iter().map(|v| string(v)).collect()));
+            }
+            Type::Ref(name) => set("$ref", string(&format!("#/definitions/{}", name))),
+            Type::RefArray(name) => {
+                set("type", string("array"));
+                set("items", reference(name));
+            }
+        }
+        schema
+    }
+}
+
+/// A config struct described by the schema
+struct Section {
+    /// Name of the struct in config.rs
+    name: &'static str,
+    /// Definition name in the schema
+    definition: &'static str,
+    /// What the section is for
+    description: &'static str,
+    /// Description of each field; one starting with "Deprecated" marks the
+    /// field deprecated
+    docs: &'static [(&'static str, &'static str)],
+    /// Field names, from serde
+    fields: fn() -> &'static [&'static str],
+    /// Fields without a default, all strings
+    required: &'static [&'static str],
+    /// Values of the fields with defaults, from serde, given the required
+    /// fields
+    defaults: fn(&[&str]) -> toml::Table,
+    /// JSON type of a field
+    field_type: fn(&str) -> Option<Type>,
+}
+
+impl Section {
+    /// Description of `field`
+    fn doc(&self, field: &str) -> Option<&'static str> {
+        self.docs.iter().find(|(name, _)| *name == field).map(|(_, doc)| *doc)
+    }
+}
+
+/// Every section with a definition, in the order they are written
+const SECTIONS: &[Section] = &[
+    Section {
+        name: "AppConfig",
+        definition: "app",
+        description: "Application metadata",
+        docs: &[
+            ("name", "Application name"),
+            ("version", "Application version"),
+            ("icon", "Application icon path (optional)"),
+            ("description", "Application description (optional)"),
+        ],
+        fields: field_names::<AppConfig>,
+        required: &["name"],
+        defaults: defaults::<AppConfig>,
+        field_type: |field| match field {
+            "name" | "version" | "icon" | "description" => Some(Type::String),
+            _ => None,
+        },
+    },
+    Section {
+        name: "BackendConfig",
+        definition: "backend",
+        description: "Backend server configuration",
+        docs: &[
+            ("name", "Backend name (defaults to the command's file name)"),
+            ("depends_on", "Names of backends that must be ready before this one starts"),
+            ("command", "Command to run the backend (e.g., \"gunicorn\", \"nginx\", \"python\")"),
+            ("args", "Arguments to pass to the command"),
+            ("socket", "Socket path (Unix), `@name` for a Linux abstract socket, or pipe name (Windows); \"auto\" (the default) picks a path under `$XDG_RUNTIME_DIR/harbor/<app>/`, and \"none\" is for a backend that does not listen, such as a worker"),
+            ("socket_activation", "Bind the socket in Harbor and pass it to the backend using the systemd `LISTEN_FDS` protocol, instead of letting the backend bind it"),
+            ("workdir", "Working directory for the backend process"),
+            ("env", "Environment variables to set"),
+            ("startup_timeout", "Startup timeout in seconds"),
+            ("restart_on_crash", "Deprecated: use `[backend.restart] policy`; `true` means \"always\", `false` means \"never\""),
+            ("restart", "Restart policy, limits and backoff"),
+            ("log_level", "Level at which captured stdout/stderr lines are logged (\"off\" to disable)"),
+            ("log_buffer_lines", "Number of recent output lines kept in memory"),
+            ("readiness", "Readiness check that must pass (after the socket accepts connections) before the backend counts as ready"),
+            ("health", "Health monitoring once the backend is running"),
+            ("stop_signal", "Signal asking the backend to stop: \"TERM\", \"INT\", \"QUIT\" or \"HUP\""),
+            ("stop_timeout", "Seconds to wait for the backend to exit, after the shutdown request (if any) and the stop signal together, before killing it"),
+            ("shutdown_path", "HTTP endpoint sent a POST to ask the backend to shut down, before the stop signal (optional)"),
+        ],
+        fields: field_names::<BackendConfig>,
+        required: &["command"],
+        defaults: defaults::<BackendConfig>,
+        field_type: |field| match field {
+            "name" | "command" | "socket" | "workdir" | "shutdown_path" => Some(Type::String),
+            "depends_on" | "args" => Some(Type::Strings),
+            "socket_activation" | "restart_on_crash" => Some(Type::Boolean),
+            "env" => Some(Type::StringMap),
+            "startup_timeout" | "log_buffer_lines" | "stop_timeout" => Some(Type::Integer),
+            "restart" => Some(Type::Ref("restart")),
+            "log_level" => Some(Type::Enum(LOG_LEVELS.to_vec())),
+            "readiness" => Some(Type::Ref("readiness")),
+            "health" => Some(Type::Ref("health")),
+            "stop_signal" => Some(Type::Enum(variant_names::<StopSignal>().to_vec())),
+            _ => None,
+        },
+    },
+    Section {
+        name: "RestartConfig",
+        definition: "restart",
+        description: "Restart configuration",
+        docs: &[
+            ("policy", "Restart policy (default: \"always\")"),
+            ("max_restarts", "Restarts allowed within `window_secs` before giving up (0 = unlimited)"),
+            ("window_secs", "Window for counting restarts, in seconds"),
+            ("backoff_initial_ms", "Delay before the first restart in milliseconds; doubles per restart"),
+            ("backoff_max_ms", "Upper bound for the restart delay in milliseconds"),
+        ],
+        fields: field_names::<RestartConfig>,
+        required: &[],
+        defaults: defaults::<RestartConfig>,
+        field_type: |field| match field {
+            "policy" => Some(Type::Enum(variant_names::<RestartPolicy>().to_vec())),
+            "max_restarts" | "window_secs" | "backoff_initial_ms" | "backoff_max_ms" => Some(Type::Integer),
+            _ => None,
+        },
+    },
+    Section {
+        name: "HealthConfig",
+        definition: "health",
+        description: "Health monitoring configuration",
+        docs: &[
+            ("interval_ms", "Delay between checks in milliseconds"),
+            ("path", "HTTP liveness endpoint (optional)"),
+            ("method", "HTTP method for the liveness request"),
+            ("status", "Expected liveness response status"),
+            ("timeout_ms", "Liveness request timeout in milliseconds"),
+            ("failure_threshold", "Consecutive liveness failures before the backend is restarted"),
+        ],
+        fields: field_names::<HealthConfig>,
+        required: &[],
+        defaults: defaults::<HealthConfig>,
+        field_type: |field| match field {
+            "path" | "method" => Some(Type::String),
+            "interval_ms" | "status" | "timeout_ms" | "failure_threshold" => Some(Type::Integer),
+            _ => None,
+        },
+    },
+    Section {
+        name: "HttpReadinessConfig",
+        definition: "readiness-http",
+        description: "HTTP readiness probe configuration",
+        docs: &[
+            ("method", "HTTP method"),
+            ("path", "Request path"),
+            ("status", "Expected response status"),
+            ("body_contains", "Text the response body must contain (optional)"),
+            ("interval_ms", "Delay between attempts in milliseconds"),
+        ],
+        fields: field_names::<HttpReadinessConfig>,
+        required: &[],
+        defaults: defaults::<HttpReadinessConfig>,
+        field_type: |field| match field {
+            "method" | "path" | "body_contains" => Some(Type::String),
+            "status" | "interval_ms" => Some(Type::Integer),
+            _ => None,
+        },
+    },
+    Section {
+        name: "LogLineReadinessConfig",
+        definition: "readiness-log-line",
+        description: "Log-line readiness configuration",
+        docs: &[("pattern", "Regular expression matched against each stdout/stderr line")],
+        fields: field_names::<LogLineReadinessConfig>,
+        required: &["pattern"],
+        defaults: defaults::<LogLineReadinessConfig>,
+        field_type: |field| (field == "pattern").then_some(Type::String),
+    },
+    Section {
+        name: "FileReadinessConfig",
+        definition: "readiness-file",
+        description: "Marker-file readiness configuration",
+        docs: &[("path", "File the backend creates once it is ready (removed before start)")],
+        fields: field_names::<FileReadinessConfig>,
+        required: &["path"],
+        defaults: defaults::<FileReadinessConfig>,
+        field_type: |field| (field == "path").then_some(Type::String),
+    },
+    Section {
+        name: "NotifyReadinessConfig",
+        definition: "readiness-notify",
+        description: "sd_notify readiness configuration",
+        docs: &[],
+        fields: || &[],
+        required: &[],
+        defaults: |_| toml::Table::new(),
+        field_type: |_| None,
+    },
+    Section {
+        name: "FrontendConfig",
+        definition: "frontend",
+        description: "Frontend window configuration",
+        docs: &[
+            ("url", "URL to load (transport-aware URL) Example: \"http::unix///tmp/app.sock/\", \"http::unix//@myapp/\" or \"http::pipe//myapp/\""),
+            ("width", "Window width"),
+            ("height", "Window height"),
+            ("title", "Window title (defaults to app name)"),
+            ("resizable", "Whether the window is resizable"),
+            ("decorated", "Whether to show the window frame"),
+            ("fullscreen", "Whether to start fullscreen"),
+            ("min_size", "Minimum window size"),
+            ("max_size", "Maximum window size"),
+        ],
+        fields: field_names::<FrontendConfig>,
+        required: &["url"],
+        defaults: defaults::<FrontendConfig>,
+        field_type: |field| match field {
+            "url" | "title" => Some(Type::String),
+            "width" | "height" => Some(Type::Integer),
+            "resizable" | "decorated" | "fullscreen" => Some(Type::Boolean),
+            "min_size" | "max_size" => Some(Type::Size),
+            _ => None,
+        },
+    },
+    Section {
+        name: "SettingsConfig",
+        definition: "settings",
+        description: "Additional settings",
+        docs: &[
+            ("devtools", "Enable developer tools"),
+            ("log_level", "Log level (off, trace, debug, info, warn, error)"),
+            ("user_agent", "Custom user agent string"),
+        ],
+        fields: field_names::<SettingsConfig>,
+        required: &[],
+        defaults: defaults::<SettingsConfig>,
+        field_type: |field| match field {
+            "devtools" => Some(Type::Boolean),
+            "log_level" => Some(Type::Enum(LOG_LEVELS.to_vec())),
+            "user_agent" => Some(Type::String),
+            _ => None,
+        },
+    },
+    Section {
+        name: "LoggingConfig",
+        definition: "logging",
+        description: "Log file configuration",
+        docs: &[
+            ("enabled", "Whether to write log files"),
+            ("dir", "Directory for this app's log files (overrides the XDG state directory)"),
+            ("max_size_mb", "Rotate when a file exceeds this size in megabytes (0 disables)"),
+            ("max_age_hours", "Rotate when a file is older than this many hours"),
+            ("keep", "Number of rotated files to keep"),
+        ],
+        fields: field_names::<LoggingConfig>,
+        required: &[],
+        defaults: defaults::<LoggingConfig>,
+        field_type: |field| match field {
+            "enabled" => Some(Type::Boolean),
+            "dir" => Some(Type::String),
+            "max_size_mb" | "max_age_hours" | "keep" => Some(Type::Integer),
+            _ => None,
+        },
+    },
+];
+
+/// The top level of app.toml
+const ROOT: Section = Section {
+    name: "HarborConfig",
+    definition: "",
+    description: "Main Harbor configuration",
+    docs: &[
+        ("config_version", "Layout version of the file; older layouts are upgraded on load"),
+        ("app", "Application metadata"),
+        ("backend", "Backend server configuration (single-backend form)"),
+        ("backends", "Named backend servers (`[[backends]]`), started in dependency order"),
+        ("frontend", "Frontend window configuration"),
+        ("settings", "Optional: Additional settings"),
+        ("logging", "Optional: Log file settings"),
+    ],
+    fields: field_names::<HarborConfig>,
+    required: &["app", "frontend"],
+    defaults: |_| {
+        let mut defaults = toml::Table::new();
+        defaults.insert(VERSION_KEY.to_string(), toml::Value::Integer(CONFIG_VERSION));
+        defaults
+    },
+    field_type: |field| match field {
+        VERSION_KEY => Some(Type::Integer),
+        "app" => Some(Type::Ref("app")),
+        "backend" => Some(Type::Ref("backend")),
+        "backends" => Some(Type::RefArray("backend")),
+        "frontend" => Some(Type::Ref("frontend")),
+        "settings" => Some(Type::Ref("settings")),
+        "logging" => Some(Type::Ref("logging")),
+        _ => None,
+    },
+};
+
+/// Serialized fields of `T` with only the `required` fields set, less
+/// those: the values of the fields that have defaults
+fn defaults<T: for<'de> Deserialize<'de> + Serialize>(required: &[&str]) -> toml::Table {
+    let value = toml::Value::Table(required_only(required))
+        .try_into::<T>()
+        .ok()
+        .and_then(|section| toml::Table::try_from(section).ok())
+        .unwrap_or_default();
+    value
+        .into_iter()
+        .filter(|(key, value)| !required.contains(&key.as_str()) && !matches!(value, toml::Value::Table(t) if !t.is_empty()))
+        .collect()
+}
+
+/// A section with only the `required` fields, set to empty strings
+fn required_only(required: &[&str]) -> toml::Table {
+    required
+        .iter()
+        .map(|key| (key.to_string(), toml::Value::String(String::new())))
+        .collect()
+}
+
+/// Schema of the object described by `section`
+fn object_schema(section: &Section) -> toml::Table {
+    let defaults = (section.defaults)(section.required);
+
+    let mut properties = toml::Table::new();
+    for field in (section.fields)() {
+        let mut schema = (section.field_type)(field).map(|t| t.to_schema()).unwrap_or_default();
+        if let Some(doc) = section.doc(field) {
+            schema.insert("description".to_string(), toml::Value::String(doc.to_string()));
+            if doc.starts_with("Deprecated") {
+                schema.insert("deprecated".to_string(), toml::Value::Boolean(true));
+            }
+        }
+        if let Some(default) = defaults.get(*field) {
+            schema.insert("default".to_string(), default.clone());
+        }
+        properties.insert(field.to_string(), toml::Value::Table(schema));
+    }
+
+    let mut object = toml::Table::new();
+    object.insert("type".to_string(), toml::Value::String("object".to_string()));
+    object.insert("description".to_string(), toml::Value::String(section.description.to_string()));
+    object.insert("properties".to_string(), toml::Value::Table(properties));
+    if !section.required.is_empty() {
+        let required = section.required.iter().map(|r| toml::Value::String(r.to_string()));
+        object.insert("required".to_string(), toml::Value::Array(required.collect()));
+    }
+    object.insert("additionalProperties".to_string(), toml::Value::Boolean(false));
+    object
+}
+
+/// Schema of `[backend.readiness]`: one of the strategies, by `type`
+fn readiness_schema() -> toml::Table {
+    let strategies = READINESS_TYPES.iter().map(|(name, section)| {
+        let mut kind = Type::Enum(vec![name]).to_schema();
+        kind.insert(
+            "description".to_string(),
+            toml::Value::String("Readiness strategy".to_string()),
+        );
+
+        let mut strategy = SECTIONS
+            .iter()
+            .find(|s| s.name == *section)
+            .map(object_schema)
+            .unwrap_or_default();
+        if let Some(toml::Value::Table(properties)) = strategy.get_mut("properties") {
+            properties.insert("type".to_string(), toml::Value::Table(kind));
+        }
+        // A table without `type` is an HTTP probe
+        if *name != "http" {
+            let required = strategy
+                .entry("required")
+                .or_insert_with(|| toml::Value::Array(Vec::new()));
+            if let toml::Value::Array(required) = required {
+                required.insert(0, toml::Value::String("type".to_string()));
+            }
+        }
+        toml::Value::Table(strategy)
+    });
+
+    let mut schema = toml::Table::new();
+    schema.insert("description".to_string(), toml::Value::String(READINESS_DESCRIPTION.to_string()));
+    schema.insert("anyOf".to_string(), toml::Value::Array(strategies.collect()));
+    schema
+}
+
+/// Build the JSON Schema for app.toml
+pub fn schema() -> toml::Table {
+    let mut definitions = toml::Table::new();
+    for section in SECTIONS.iter().filter(|s| !s.definition.starts_with("readiness-")) {
+        definitions.insert(section.definition.to_string(), toml::Value::Table(object_schema(section)));
+    }
+    definitions.insert("readiness".to_string(), toml::Value::Table(readiness_schema()));
+
+    let mut root = object_schema(&ROOT);
+    let Some(toml::Value::Table(properties)) = root.get_mut("properties") else {
+        unreachable!("object schemas have properties");
+    };
+    if let Some(toml::Value::Table(version)) = properties.get_mut(VERSION_KEY) {
+        version.insert("minimum".to_string(), toml::Value::Integer(1));
+        version.insert("maximum".to_string(), toml::Value::Integer(CONFIG_VERSION));
+    }
+    let mut extends = Type::String.to_schema();
+    extends.insert(
+        "description".to_string(),
+        toml::Value::String("Config file whose settings this one extends, relative to this file".to_string()),
+    );
+    properties.insert(EXTENDS_KEY.to_string(), toml::Value::Table(extends));
+
+    // A profile holds any of the top-level keys
+    let mut profile = properties.clone();
+    profile.remove(VERSION_KEY);
+    profile.remove(EXTENDS_KEY);
+    let mut profile_schema = toml::Table::new();
+    profile_schema.insert("type".to_string(), toml::Value::String("object".to_string()));
+    profile_schema.insert("properties".to_string(), toml::Value::Table(profile));
+    profile_schema.insert("additionalProperties".to_string(), toml::Value::Boolean(false));
+    let mut profiles = toml::Table::new();
+    profiles.insert("type".to_string(), toml::Value::String("object".to_string()));
+    profiles.insert(
+        "description".to_string(),
+        toml::Value::String("Overrides selected with --profile <name> or $HARBOR_PROFILE".to_string()),
+    );
+    profiles.insert("additionalProperties".to_string(), toml::Value::Table(profile_schema));
+    properties.insert("profile".to_string(), toml::Value::Table(profiles));
+
+    let string = |s: &str| toml::Value::String(s.to_string());
+    root.insert("$schema".to_string(), string("http://json-schema.org/draft-07/schema#"));
+    root.insert("title".to_string(), string("Harbor app.toml"));
+    root.insert("definitions".to_string(), toml::Value::Table(definitions));
+    root
+}
+
+/// The JSON Schema for app.toml, as JSON
+pub fn schema_json() -> String {
+    let mut json = String::new();
+    crate::show::write_json(&toml::Value::Table(schema()), 0, &mut json);
+    json.push('\n');
+    json
+}
+
+/// Checks config values against the schema
+pub struct Checker {
+    root: toml::Table,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self { root: schema() }
+    }
+}
+
+impl Checker {
+    /// Get the schema of the value at `path`, and of the table holding it
+    ///
+    /// Where a key is in more than one alternative of an `anyOf`, such as
+    /// `type` in `[backend.readiness]`, its schema is any of theirs.
+    pub fn lookup(&self, path: &[Segment]) -> anyhow::Result<(toml::Table, toml::Table)> {
+        self.lookup_from(&self.root, path)
+    }
+
+    /// Like `lookup`, but starting from `schema` rather than the root
+    fn lookup_from(&self, schema: &toml::Table, path: &[Segment]) -> anyhow::Result<(toml::Table, toml::Table)> {
+        let mut schema = schema.clone();
+        let mut parent = schema.clone();
+        for (depth, segment) in path.iter().enumerate() {
+            let key = || format_key_path(&path[..=depth]);
+            let current = self.resolve(&schema).clone();
+            match segment {
+                Segment::Key(name) => {
+                    let alternatives = current.get("anyOf").and_then(toml::Value::as_array);
+                    let objects = std::iter::once(&current)
+                        .chain(alternatives.into_iter().flatten().filter_map(toml::Value::as_table));
+                    let mut known = Vec::new();
+                    let mut found: Vec<(&toml::Table, &toml::Value)> = Vec::new();
+                    for object in objects {
+                        let properties = object.get("properties").and_then(toml::Value::as_table);
+                        let property = properties
+                            .and_then(|p| p.get(name))
+                            .or_else(|| object.get("additionalProperties").filter(|a| a.is_table()));
+                        match property {
+                            Some(property) if !found.iter().any(|(_, p)| *p == property) => {
+                                found.push((object, property))
+                            }
+                            Some(_) => {}
+                            None => known.extend(properties.into_iter().flat_map(|p| p.keys()).map(String::as_str)),
+                        }
+                    }
+
+                    let Some((object, property)) = found.first() else {
+                        known.sort_unstable();
+                        known.dedup();
+                        match suggestion(name, &known) {
+                            Some(similar) => bail!("{}: unknown key, did you mean '{}'?", key(), similar),
+                            None => bail!("{}: unknown key (expected one of: {})", key(), known.join(", ")),
+                        }
+                    };
+                    parent = (*object).clone();
+                    schema = match found.len() {
+                        1 => property.as_table().cloned().unwrap_or_default(),
+                        _ => {
+                            let any = found.iter().map(|(_, p)| (*p).clone()).collect();
+                            toml::Table::from_iter([("anyOf".to_string(), toml::Value::Array(any))])
+                        }
+                    };
+                }
+                Segment::Index(_) => {
+                    schema = current
+                        .get("items")
+                        .and_then(toml::Value::as_table)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("{}: {} is not an array", key(), format_key_path(&path[..depth])))?;
+                }
+            }
+        }
+        Ok((self.resolve(&schema).clone(), parent))
+    }
+
+    /// Check `value`, the value of `key`, against `schema`
+    pub fn check(&self, schema: &toml::Table, value: &toml::Value, key: &str) -> anyhow::Result<()> {
+        let schema = self.resolve(schema);
+
+        if let Some(alternatives) = schema.get("anyOf").and_then(toml::Value::as_array) {
+            let matches = alternatives
+                .iter()
+                .filter_map(toml::Value::as_table)
+                .any(|alternative| self.check(alternative, value, key).is_ok());
+            if matches {
+                return Ok(());
+            }
+            // Alternatives that are all lists of values are one longer list
+            let allowed: Option<Vec<&toml::Value>> = alternatives
+                .iter()
+                .map(|alternative| alternative.get("enum").and_then(toml::Value::as_array))
+                .collect::<Option<Vec<_>>>()
+                .map(|lists| lists.into_iter().flatten().collect());
+            match allowed {
+                Some(allowed) => {
+                    let names: Vec<String> = allowed.iter().map(|v| v.to_string()).collect();
+                    bail!("{}: {} is not one of {}", key, value, names.join(", "));
+                }
+                None => bail!("{}: {} does not match any of the allowed forms", key, value),
+            }
+        }
+
+        let expected = schema.get("type").and_then(toml::Value::as_str).unwrap_or_default();
+        match (expected, value) {
+            ("string", toml::Value::String(_)) | ("boolean", toml::Value::Boolean(_)) => {}
+            ("integer", toml::Value::Integer(i)) => {
+                let bound = |name: &str| schema.get(name).and_then(toml::Value::as_integer);
+                if let Some(min) = bound("minimum").filter(|min| i < min) {
+                    bail!("{}: {} is less than the minimum, {}", key, i, min);
+                }
+                if let Some(max) = bound("maximum").filter(|max| i > max) {
+                    bail!("{}: {} is more than the maximum, {}", key, i, max);
+                }
+            }
+            ("array", toml::Value::Array(items)) => {
+                let count = |name: &str| schema.get(name).and_then(toml::Value::as_integer);
+                if count("minItems").is_some_and(|min| (items.len() as i64) < min)
+                    || count("maxItems").is_some_and(|max| (items.len() as i64) > max)
+                {
+                    bail!("{}: {} has the wrong number of items", key, value);
+                }
+                if let Some(item_schema) = schema.get("items").and_then(toml::Value::as_table) {
+                    for (i, item) in items.iter().enumerate() {
+                        self.check(item_schema, item, &format!("{}[{}]", key, i))?;
+                    }
+                }
+            }
+            ("object", toml::Value::Table(table)) => {
+                for (name, item) in table {
+                    let item_key = join_key(key, name);
+                    let (item_schema, _) = self
+                        .lookup_from(schema, &[Segment::Key(name.clone())])
+                        .map_err(|_| anyhow!("{}: unknown key", item_key))?;
+                    self.check(&item_schema, item, &item_key)?;
+                }
+                let required = schema.get("required").and_then(toml::Value::as_array);
+                for name in required.into_iter().flatten().filter_map(toml::Value::as_str) {
+                    if !table.contains_key(name) {
+                        bail!("{}: missing required key '{}'", key, name);
+                    }
+                }
+            }
+            ("", _) => {}
+            (expected, value) => bail!("{}: expected {} {}, got {}", key, article(expected), expected, value),
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(toml::Value::as_array) {
+            if !allowed.contains(value) {
+                let names: Vec<String> = allowed.iter().map(|v| v.to_string()).collect();
+                bail!("{}: {} is not one of {}", key, value, names.join(", "));
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow a `$ref` to its definition
+    fn resolve<'a>(&'a self, schema: &'a toml::Table) -> &'a toml::Table {
+        let reference = schema
+            .get("$ref")
+            .and_then(toml::Value::as_str)
+            .and_then(|r| r.strip_prefix("#/definitions/"));
+        let definitions = self.root.get("definitions").and_then(toml::Value::as_table);
+        match (reference, definitions) {
+            (Some(name), Some(definitions)) => definitions
+                .get(name)
+                .and_then(toml::Value::as_table)
+                .unwrap_or(schema),
+            _ => schema,
+        }
+    }
+}
+
+fn article(kind: &str) -> &'static str {
+    match kind {
+        "integer" | "array" | "object" => "an",
+        _ => "a",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReadinessConfig;
+
+    #[test]
+    fn test_schema_in_sync() {
+        // Every field serde reads has a type and a description, and every
+        // description is of such a field
+        for section in SECTIONS.iter().chain([&ROOT]) {
+            for field in (section.fields)() {
+                assert!((section.field_type)(field).is_some(), "{}.{} has no schema type", section.name, field);
+                assert!(section.doc(field).is_some_and(|d| !d.is_empty()), "{}.{} has no doc", section.name, field);
+            }
+            for (field, _) in section.docs {
+                assert!((section.fields)().contains(field), "{}.{} is not a field", section.name, field);
+            }
+        }
+
+        // Exactly the required fields are needed to deserialize a section
+        for section in SECTIONS {
+            let (defaults, required) = ((section.defaults)(section.required), section.required);
+            assert!(!defaults.is_empty() || (section.fields)().len() == required.len(), "{} does not deserialize", section.name);
+            for field in required {
+                let fewer: Vec<&str> = required.iter().copied().filter(|r| r != field).collect();
+                assert!((section.defaults)(&fewer).is_empty(), "{}.{} is not required", section.name, field);
+            }
+        }
+
+        // Every readiness type deserializes
+        for (name, _) in READINESS_TYPES {
+            let table = format!("type = '{}'\npattern = 'x'\npath = 'x'", name);
+            let strategy: Result<ReadinessConfig, _> = toml::from_str(&table);
+            assert!(strategy.is_ok(), "readiness type {} is not known", name);
+        }
+
+        let schema = schema();
+        let backend = &schema["definitions"]["backend"]["properties"];
+        assert_eq!(backend["startup_timeout"]["default"].as_integer(), Some(30));
+        assert_eq!(backend["startup_timeout"]["description"].as_str(), Some("Startup timeout in seconds"));
+        assert_eq!(backend["restart_on_crash"]["deprecated"].as_bool(), Some(true));
+        assert!(backend["command"].get("default").is_none());
+        let policies: Vec<&str> = schema["definitions"]["restart"]["properties"]["policy"]["enum"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(toml::Value::as_str)
+            .collect();
+        assert_eq!(policies, vec!["always", "on-failure", "never"]);
+
+        // The output is valid JSON
+        assert_eq!(crate::manifest::parse_json(&schema_json()).unwrap(), toml::Value::Table(schema));
+    }
+}
@@ -0,0 +1,175 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Printing the resolved configuration
+//!
+//! `harbor config show` prints every value Harbor will use, defaults
+//! included, with where it came from: the default, a line of app.toml or
+//! of the user's override file, a profile, an environment variable or a
+//! `--set` flag.
+
+use crate::config::HarborConfig;
+use crate::interpolate::join_key;
+use std::fmt::Write;
+
+impl HarborConfig {
+    /// The resolved configuration as TOML, each value followed by a
+    /// comment naming its source
+    pub fn to_annotated_toml(&self) -> anyhow::Result<String> {
+        let mut document: toml_edit::DocumentMut = toml::to_string_pretty(self)?.parse()?;
+        self.annotate_table(document.as_table_mut(), "");
+        Ok(document.to_string())
+    }
+
+    /// The resolved configuration as JSON
+    ///
+    /// The object has two members: `config`, the configuration itself, and
+    /// `sources`, the source of each value by key path.
+    pub fn to_annotated_json(&self) -> anyhow::Result<String> {
+        let config = toml::Value::try_from(self)?;
+        let mut leaves = Vec::new();
+        collect_leaves(&config, "", &mut leaves);
+
+        let mut json = String::from("{\n  \"config\": ");
+        write_json(&config, 1, &mut json);
+        json.push_str(",\n  \"sources\": {");
+        for (i, key) in leaves.iter().enumerate() {
+            json.push_str(if i == 0 { "\n    " } else { ",\n    " });
+            write_json_string(key, &mut json);
+            json.push_str(": ");
+            write_json_string(&self.sources.source_of(key).to_string(), &mut json);
+        }
+        json.push_str("\n  }\n}\n");
+        Ok(json)
+    }
+
+    fn annotate_table(&self, table: &mut dyn toml_edit::TableLike, key: &str) {
+        for (name, item) in table.iter_mut() {
+            let path = join_key(key, &name);
+            match item {
+                toml_edit::Item::Table(table) => self.annotate_table(table, &path),
+                toml_edit::Item::ArrayOfTables(tables) => {
+                    for (i, table) in tables.iter_mut().enumerate() {
+                        self.annotate_table(table, &format!("{}[{}]", path, i));
+                    }
+                }
+                toml_edit::Item::Value(value) => {
+                    let source = self.sources.source_of(&path);
+                    value.decor_mut().set_suffix(format!("  # {}", source));
+                }
+                toml_edit::Item::None => {}
+            }
+        }
+    }
+}
+
+/// Key paths of the values in `value`, at `key`, as provenance records
+/// them: arrays of tables are descended into, other arrays are values
+fn collect_leaves(value: &toml::Value, key: &str, leaves: &mut Vec<String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (name, item) in table {
+                collect_leaves(item, &join_key(key, name), leaves);
+            }
+        }
+        toml::Value::Array(items) if !items.is_empty() && items.iter().all(toml::Value::is_table) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_leaves(item, &format!("{}[{}]", key, i), leaves);
+            }
+        }
+        _ => leaves.push(key.to_string()),
+    }
+}
+
+/// Write `value` as JSON, indented for nesting `depth`
+pub(crate) fn write_json(value: &toml::Value, depth: usize, out: &mut String) {
+    let indent = |depth: usize| "  ".repeat(depth);
+    match value {
+        toml::Value::String(s) => write_json_string(s, out),
+        toml::Value::Integer(i) => {
+            let _ = write!(out, "{}", i);
+        }
+        toml::Value::Float(f) if f.is_finite() => {
+            let _ = write!(out, "{:?}", f);
+        }
+        toml::Value::Float(_) => out.push_str("null"),
+        toml::Value::Boolean(b) => {
+            let _ = write!(out, "{}", b);
+        }
+        toml::Value::Datetime(d) => write_json_string(&d.to_string(), out),
+        toml::Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        toml::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&indent(depth + 1));
+                write_json(item, depth + 1, out);
+            }
+            let _ = write!(out, "\n{}]", indent(depth));
+        }
+        toml::Value::Table(table) if table.is_empty() => out.push_str("{}"),
+        toml::Value::Table(table) => {
+            out.push('{');
+            for (i, (name, item)) in table.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&indent(depth + 1));
+                write_json_string(name, out);
+                out.push_str(": ");
+                write_json(item, depth + 1, out);
+            }
+            let _ = write!(out, "\n{}}}", indent(depth));
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{HarborConfig, LoadOptions};
+
+    #[test]
+    fn test_annotated_output() {
+        let toml = r#"
+[app]
+name = "Shown"
+
+[backend]
+command = "sh"
+socket = "/tmp/shown.sock"
+
+[frontend]
+url = "http::unix///tmp/shown.sock/"
+title = "Say \"hi\""
+"#;
+        let options = LoadOptions::default().with_set("frontend.width=1200");
+        let config = HarborConfig::from_str_with(toml, "/srv/shown", &options).unwrap();
+
+        let annotated = config.to_annotated_toml().unwrap();
+        assert!(annotated.contains("name = \"Shown\"  # app.toml:3\n"));
+        assert!(annotated.contains("startup_timeout = 30  # default\n"));
+        assert!(annotated.contains("width = 1200  # --set frontend.width=1200\n"));
+
+        let json = config.to_annotated_json().unwrap();
+        assert!(json.contains("\"title\": \"Say \\\"hi\\\"\""));
+        assert!(json.contains("\"frontend.url\": \"app.toml:10\""));
+        assert!(json.contains("\"backend.startup_timeout\": \"default\""));
+    }
+}
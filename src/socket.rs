@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Backend socket addresses
+//!
+//! `backend.socket` is either a filesystem path or, on Linux, the name of
+//! an abstract-namespace socket written as `@name`. Abstract sockets have
+//! no file: nothing needs cleaning up and no stale file can get in the way
+//! of the next start. A backend that does not listen at all, such as a
+//! worker, has `socket = "none"`.
+//!
+//! Socket files are checked before use: the directory must not let other
+//! users replace them, Harbor only removes stale sockets it owns, and a
+//! ready backend's socket must be owned by the current user with no group
+//! or world access.
+
+use crate::backend::BackendError;
+use log::{debug, warn};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Address of a backend's Unix socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendSocket {
+    /// A socket file
+    Path(PathBuf),
+    /// An abstract socket name, without the leading `@`
+    Abstract(String),
+    /// No socket: the backend does not listen
+    Disabled,
+}
+
+impl BackendSocket {
+    /// Parse a `backend.socket` value
+    pub fn parse(socket: &str) -> Self {
+        if socket == crate::config::NO_SOCKET {
+            return BackendSocket::Disabled;
+        }
+        match socket.strip_prefix('@') {
+            Some(name) => BackendSocket::Abstract(name.to_string()),
+            None => BackendSocket::Path(PathBuf::from(socket)),
+        }
+    }
+
+    /// Get the socket file path, if this socket has one
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            BackendSocket::Path(path) => Some(path),
+            BackendSocket::Abstract(_) | BackendSocket::Disabled => None,
+        }
+    }
+
+    /// Whether this is an abstract socket
+    pub fn is_abstract(&self) -> bool {
+        matches!(self, BackendSocket::Abstract(_))
+    }
+
+    /// Whether the backend has no socket
+    pub fn is_disabled(&self) -> bool {
+        matches!(self, BackendSocket::Disabled)
+    }
+
+    /// Connect to the socket
+    #[cfg(unix)]
+    pub fn connect(&self) -> std::io::Result<std::os::unix::net::UnixStream> {
+        use std::os::unix::net::UnixStream;
+
+        match self {
+            BackendSocket::Path(path) => UnixStream::connect(path),
+            BackendSocket::Abstract(name) => UnixStream::connect_addr(&abstract_addr(name)?),
+            BackendSocket::Disabled => Err(no_socket()),
+        }
+    }
+
+    /// Create the socket's directory, accessible only to the current user,
+    /// if it does not exist yet
+    pub fn create_dir(&self) -> std::io::Result<()> {
+        let Some(dir) = self.path().and_then(Path::parent) else {
+            return Ok(());
+        };
+        if dir.as_os_str().is_empty() || dir.exists() {
+            return Ok(());
+        }
+
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(dir)
+    }
+
+    /// Fail if other users could replace the socket file
+    ///
+    /// A world-writable directory is only acceptable with the sticky bit
+    /// set (as on `/tmp`), which stops others from removing our files.
+    pub fn check_dir(&self) -> Result<(), BackendError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let Some(dir) = self.path().and_then(Path::parent) else {
+                return Ok(());
+            };
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            let Ok(metadata) = std::fs::metadata(dir) else {
+                // A missing directory shows up as a bind failure instead
+                return Ok(());
+            };
+
+            let mode = metadata.permissions().mode();
+            if mode & 0o002 != 0 && mode & 0o1000 == 0 {
+                return Err(BackendError::InsecureSocketDir {
+                    dir: dir.to_path_buf(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a socket file left over from an earlier run
+    ///
+    /// Refuses to remove anything that is not a socket owned by the
+    /// current user. Symlinks are not followed.
+    pub fn remove_stale(&self) -> Result<(), BackendError> {
+        let Some(path) = self.path() else {
+            return Ok(());
+        };
+        let metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+            if !metadata.file_type().is_socket() {
+                return Err(BackendError::NotASocket {
+                    path: path.to_path_buf(),
+                });
+            }
+            if metadata.uid() != current_uid() {
+                return Err(BackendError::ForeignSocket {
+                    path: path.to_path_buf(),
+                    owner: metadata.uid(),
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+
+        debug!("Removing stale socket: {}", path.display());
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Check that a ready socket belongs to the current user and restrict
+    /// it to mode 0600
+    pub fn secure(&self) -> Result<(), BackendError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+            let Some(path) = self.path() else {
+                return Ok(());
+            };
+            let metadata = std::fs::symlink_metadata(path)?;
+            if !metadata.file_type().is_socket() {
+                return Err(BackendError::NotASocket {
+                    path: path.to_path_buf(),
+                });
+            }
+            if metadata.uid() != current_uid() {
+                return Err(BackendError::SocketOwner {
+                    path: path.to_path_buf(),
+                    owner: metadata.uid(),
+                });
+            }
+
+            let mode = metadata.mode() & 0o777;
+            if mode & 0o077 != 0 {
+                warn!(
+                    "Socket {} has mode {:o}, restricting to 600",
+                    path.display(),
+                    mode
+                );
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                    .map_err(|source| BackendError::SocketPermissions {
+                        path: path.to_path_buf(),
+                        source,
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind a listener on the socket
+    ///
+    /// A socket file is created with mode 0600.
+    #[cfg(unix)]
+    pub fn bind(&self) -> std::io::Result<std::os::unix::net::UnixListener> {
+        use std::os::unix::net::UnixListener;
+
+        match self {
+            BackendSocket::Path(path) => bind_private(path, |path| UnixListener::bind(path)),
+            BackendSocket::Abstract(name) => UnixListener::bind_addr(&abstract_addr(name)?),
+            BackendSocket::Disabled => Err(no_socket()),
+        }
+    }
+
+    /// Bind a datagram socket, such as an sd_notify socket
+    ///
+    /// A socket file is created with mode 0600.
+    #[cfg(unix)]
+    pub fn bind_datagram(&self) -> std::io::Result<std::os::unix::net::UnixDatagram> {
+        use std::os::unix::net::UnixDatagram;
+
+        match self {
+            BackendSocket::Path(path) => bind_private(path, |path| UnixDatagram::bind(path)),
+            BackendSocket::Abstract(name) => UnixDatagram::bind_addr(&abstract_addr(name)?),
+            BackendSocket::Disabled => Err(no_socket()),
+        }
+    }
+}
+
+impl fmt::Display for BackendSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendSocket::Path(path) => write!(f, "{}", path.display()),
+            BackendSocket::Abstract(name) => write!(f, "@{}", name),
+            BackendSocket::Disabled => f.write_str(crate::config::NO_SOCKET),
+        }
+    }
+}
+
+/// The error connecting to or binding a disabled socket
+#[cfg(unix)]
+fn no_socket() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, "the backend has no socket")
+}
+
+/// Bind a socket file at `path` that only the current user can use
+///
+/// The socket is bound inside a fresh mode 0700 directory next to `path`,
+/// restricted to 0600 and only then linked into place, so it is never
+/// reachable by other users, whatever the umask. Like a plain bind, this
+/// fails if `path` exists.
+#[cfg(unix)]
+fn bind_private<T>(path: &Path, bind: impl FnOnce(&Path) -> std::io::Result<T>) -> std::io::Result<T> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let private = dir.join(format!(
+        ".harbor-bind-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let temp = private.join("s");
+    let result = bind(&temp).and_then(|socket| {
+        std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::hard_link(&temp, path)?;
+        Ok(socket)
+    });
+    let _ = std::fs::remove_file(&temp);
+    let _ = std::fs::remove_dir(&private);
+    result
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    nix::unistd::geteuid().as_raw()
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> std::io::Result<std::os::unix::net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn abstract_addr(_name: &str) -> std::io::Result<std::os::unix::net::SocketAddr> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "abstract sockets are only available on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            BackendSocket::parse("/tmp/app.sock"),
+            BackendSocket::Path(PathBuf::from("/tmp/app.sock"))
+        );
+        let socket = BackendSocket::parse("@harbor-app");
+        assert_eq!(socket, BackendSocket::Abstract("harbor-app".to_string()));
+        assert!(socket.path().is_none());
+        assert_eq!(socket.to_string(), "@harbor-app");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_file_checks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("harbor-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.sock");
+        let socket = BackendSocket::Path(path.clone());
+        let set_mode = |p: &Path, mode| {
+            std::fs::set_permissions(p, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+
+        set_mode(&dir, 0o777);
+        assert!(matches!(socket.check_dir(), Err(BackendError::InsecureSocketDir { .. })));
+        set_mode(&dir, 0o1777);
+        assert!(socket.check_dir().is_ok());
+        set_mode(&dir, 0o700);
+
+        // Nothing there yet, then something that isn't a socket
+        assert!(socket.remove_stale().is_ok());
+        std::fs::write(&path, "data").unwrap();
+        assert!(matches!(socket.remove_stale(), Err(BackendError::NotASocket { .. })));
+        assert!(matches!(socket.secure(), Err(BackendError::NotASocket { .. })));
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = socket.bind().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(socket.bind().is_err());
+        set_mode(&path, 0o777);
+        socket.secure().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(listener);
+        socket.remove_stale().unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_connect() {
+        let socket = BackendSocket::parse(&format!("@harbor-test-{}", std::process::id()));
+        assert!(socket.connect().is_err());
+
+        let _listener = socket.bind().unwrap();
+        assert!(socket.connect().is_ok());
+    }
+}
@@ -0,0 +1,354 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Semantic configuration checks
+//!
+//! Deserializing app.toml only proves that it has the right shape.
+//! `HarborConfig::validate` looks at what the values mean: whether the
+//! command can be found, the frontend points at a backend, directories
+//! exist and sizes make sense. Each problem is reported as a diagnostic
+//! naming the key it concerns.
+
+use crate::config::{BackendConfig, HarborConfig, ReadinessConfig};
+use log::LevelFilter;
+use std::fmt;
+use std::path::Path;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The app cannot run with this configuration
+    Error,
+    /// Probably a mistake, but the app can still run
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// How serious the problem is
+    pub severity: Severity,
+    /// Dotted path of the offending key, e.g. `backends[1].workdir`
+    pub key: String,
+    /// What is wrong
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create an error diagnostic
+    pub fn error(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a warning diagnostic
+    pub fn warning(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Whether this is an error
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.key, self.message)
+    }
+}
+
+/// Whether any of `diagnostics` is an error
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(Diagnostic::is_error)
+}
+
+impl HarborConfig {
+    /// Check the configuration for semantic problems
+    ///
+    /// Returns every problem found, errors and warnings alike: unknown keys
+    /// first (as warnings), then deprecated keys that were upgraded, then
+    /// the rest, section by section: `app`, the backends, `frontend` and
+    /// `settings`. Problems with values set in an extended file or an
+    /// override say where the value came from.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.unknown_keys.clone();
+        diagnostics.extend(self.deprecations.iter().cloned());
+        let parsed = diagnostics.len();
+
+        if let Some(ref icon) = self.app.icon {
+            if let Err(e) = std::fs::File::open(icon) {
+                diagnostics.push(Diagnostic::warning(
+                    "app.icon",
+                    format!("cannot read {}: {}", icon.display(), e),
+                ));
+            }
+        }
+
+        for (key, backend) in self.backend_keys() {
+            validate_backend(&key, backend, &mut diagnostics);
+        }
+
+        self.validate_frontend(&mut diagnostics);
+
+        if self.settings.log_level.parse::<LevelFilter>().is_err() {
+            diagnostics.push(Diagnostic::error(
+                "settings.log_level",
+                format!(
+                    "invalid log level '{}' (expected off, trace, debug, info, warn or error)",
+                    self.settings.log_level
+                ),
+            ));
+        }
+
+        // Name the file or override a bad value came from, unless it is
+        // the app's own config file
+        for diagnostic in diagnostics.iter_mut().skip(parsed) {
+            let source = self.sources.get(&diagnostic.key).or_else(|| self.sources.find_within(&diagnostic.key));
+            if let Some(source) = source.filter(|s| s.is_elsewhere()) {
+                diagnostic.message = format!("{} (from {})", diagnostic.message, source);
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Like `validate`, but unknown keys are errors
+    pub fn validate_strict(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.validate();
+        for diagnostic in diagnostics.iter_mut().take(self.unknown_keys.len()) {
+            diagnostic.severity = Severity::Error;
+        }
+        diagnostics
+    }
+
+    /// Every backend with its key path
+    fn backend_keys(&self) -> Vec<(String, &BackendConfig)> {
+        let single = Some(&self.backend)
+            .filter(|b| !b.is_unset())
+            .map(|b| ("backend".to_string(), b));
+        let named = self
+            .backends
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (format!("backends[{}]", i), b));
+        single.into_iter().chain(named).collect()
+    }
+
+    fn validate_frontend(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let frontend = &self.frontend;
+
+        let sockets: Vec<&str> = self
+            .backend_configs()
+            .iter()
+            .filter(|b| b.has_socket())
+            .map(|b| b.socket.as_str())
+            .collect();
+        if !sockets.is_empty() && !sockets.iter().any(|s| frontend.connects_to(s)) {
+            diagnostics.push(Diagnostic::error(
+                "frontend.url",
+                format!(
+                    "'{}' does not point at a backend socket (use \"http::unix//${{socket}}/\")",
+                    frontend.url
+                ),
+            ));
+        }
+
+        if frontend.width == 0 || frontend.height == 0 {
+            diagnostics.push(Diagnostic::error(
+                "frontend",
+                format!(
+                    "window size {}x{} has a zero dimension",
+                    frontend.width, frontend.height
+                ),
+            ));
+        }
+        for (key, size) in [("frontend.min_size", frontend.min_size), ("frontend.max_size", frontend.max_size)] {
+            if let Some((width, height)) = size {
+                if width == 0 || height == 0 {
+                    diagnostics.push(Diagnostic::error(
+                        key,
+                        format!("{}x{} has a zero dimension", width, height),
+                    ));
+                }
+            }
+        }
+        if let (Some(min), Some(max)) = (frontend.min_size, frontend.max_size) {
+            if min.0 > max.0 || min.1 > max.1 {
+                diagnostics.push(Diagnostic::error(
+                    "frontend.min_size",
+                    format!(
+                        "{}x{} is larger than max_size {}x{}",
+                        min.0, min.1, max.0, max.1
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn validate_backend(key: &str, backend: &BackendConfig, diagnostics: &mut Vec<Diagnostic>) {
+    // Looked up on the PATH the backend will be started with
+    let path = crate::backend::search_path(backend);
+    if !command_exists(&backend.command, path.as_deref()) {
+        let message = if backend.command.contains('/') {
+            format!("{} does not exist or is not executable", backend.command)
+        } else {
+            format!("'{}' was not found on PATH", backend.command)
+        };
+        diagnostics.push(Diagnostic::error(format!("{}.command", key), message));
+    }
+
+    if let Some(ref workdir) = backend.workdir {
+        if !workdir.is_dir() {
+            diagnostics.push(Diagnostic::error(
+                format!("{}.workdir", key),
+                format!("{} is not a directory", workdir.display()),
+            ));
+        }
+    }
+
+    // Whatever needs the socket
+    if !backend.has_socket() {
+        let http_readiness = matches!(backend.readiness, Some(ReadinessConfig::Http(_)));
+        let uses = [
+            ("socket_activation", backend.socket_activation),
+            ("readiness", http_readiness),
+            ("health.path", backend.health.path.is_some()),
+            ("shutdown_path", backend.shutdown_path.is_some()),
+        ];
+        for (field, _) in uses.iter().filter(|(_, used)| *used) {
+            diagnostics.push(Diagnostic::error(
+                format!("{}.{}", key, field),
+                "needs a socket, but the backend has socket = \"none\"",
+            ));
+        }
+    }
+
+    if backend.log_level.parse::<LevelFilter>().is_err() {
+        diagnostics.push(Diagnostic::error(
+            format!("{}.log_level", key),
+            format!(
+                "invalid log level '{}' (expected off, trace, debug, info, warn or error)",
+                backend.log_level
+            ),
+        ));
+    }
+}
+
+/// Whether `command` names an executable file, directly or on `path`
+fn command_exists(command: &str, path: Option<&std::ffi::OsStr>) -> bool {
+    if command.contains('/') {
+        return is_executable(Path::new(command));
+    }
+    path.is_some_and(|path| std::env::split_paths(path).any(|dir| is_executable(&dir.join(command))))
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let toml = r#"
+            [app]
+            name = "Invalid"
+            icon = "missing.png"
+
+            [[backends]]
+            name = "web"
+            command = "/bin/sh"
+            socket = "/tmp/invalid-web.sock"
+            workdir = "missing"
+            log_level = "loud"
+
+            [[backends]]
+            name = "worker"
+            command = "harbor-test-no-such-command"
+            socket = "none"
+            shutdown_path = "/quit"
+
+            [frontend]
+            url = "http::unix///tmp/elsewhere.sock/"
+            height = 0
+            min_size = [800, 600]
+            max_size = [640, 480]
+
+            [settings]
+            log_level = "chatty"
+        "#;
+        let config = HarborConfig::from_str(toml, "/nonexistent/harbor-test").unwrap();
+        let diagnostics = config.validate();
+        let keys: Vec<(Severity, &str)> = diagnostics.iter().map(|d| (d.severity, d.key.as_str())).collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                (Severity::Warning, "app.icon"),
+                (Severity::Error, "backends[0].workdir"),
+                (Severity::Error, "backends[0].log_level"),
+                (Severity::Error, "backends[1].command"),
+                (Severity::Error, "backends[1].shutdown_path"),
+                (Severity::Error, "frontend.url"),
+                (Severity::Error, "frontend"),
+                (Severity::Error, "frontend.min_size"),
+                (Severity::Error, "settings.log_level"),
+            ]
+        );
+        assert!(has_errors(&diagnostics));
+        assert_eq!(
+            diagnostics[3].to_string(),
+            "error: backends[1].command: 'harbor-test-no-such-command' was not found on PATH"
+        );
+    }
+
+    #[test]
+    fn test_validate_clean() {
+        let toml = r#"
+            [app]
+            name = "Valid"
+
+            [backend]
+            command = "sh"
+            socket = "@harbor-valid"
+            workdir = "/"
+
+            [frontend]
+            url = "http::unix//@harbor-valid/"
+        "#;
+        let config = HarborConfig::from_str(toml, "/").unwrap();
+        assert_eq!(config.validate(), vec![]);
+    }
+}